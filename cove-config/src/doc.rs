@@ -231,6 +231,15 @@ impl Document for bool {
     }
 }
 
+impl Document for u64 {
+    fn doc() -> Doc {
+        let mut doc = Doc::default();
+        doc.value_info.required = Some(true);
+        doc.value_info.r#type = Some("integer".to_string());
+        doc
+    }
+}
+
 impl Document for PathBuf {
     fn doc() -> Doc {
         let mut doc = Doc::default();
@@ -249,6 +258,15 @@ impl<I: Document> Document for Option<I> {
     }
 }
 
+impl<I: Document> Document for Vec<I> {
+    fn doc() -> Doc {
+        let mut doc = Doc::default();
+        doc.value_info.required = Some(true);
+        doc.value_info.r#type = I::doc().value_info.r#type.map(|t| format!("list of {t}s"));
+        doc
+    }
+}
+
 impl<I: Document> Document for HashMap<String, I> {
     fn doc() -> Doc {
         let mut doc = Doc::default();
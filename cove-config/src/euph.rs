@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf};
 
 use serde::{Deserialize, Serialize};
 
@@ -12,6 +12,32 @@ pub enum RoomsSortOrder {
     Importance,
 }
 
+#[derive(Debug, Clone, Copy, Default, Deserialize, Document)]
+pub struct Retention {
+    /// Messages older than this many days are deleted from the vault. If not
+    /// set, messages are kept regardless of their age.
+    pub max_age_days: Option<u64>,
+
+    /// Only this many of the newest messages of a room are kept in the vault,
+    /// older messages are deleted. If not set, messages are kept regardless of
+    /// their amount.
+    pub max_count: Option<u64>,
+}
+
+impl Retention {
+    /// Fill in options that are not set with the options from `fallback`.
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            max_age_days: self.max_age_days.or(fallback.max_age_days),
+            max_count: self.max_count.or(fallback.max_count),
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.max_age_days.is_none() && self.max_count.is_none()
+    }
+}
+
 // TODO Mark favourite rooms via printable ascii characters
 #[derive(Debug, Clone, Default, Deserialize, Document)]
 pub struct EuphRoom {
@@ -32,16 +58,65 @@ pub struct EuphRoom {
     /// If set, cove will try once to use this password to authenticate, should
     /// the room be password-protected.
     pub password: Option<String>,
+
+    /// Overrides `euph.retention` for this room.
+    #[serde(default)]
+    #[document(no_default)]
+    pub retention: Retention,
 }
 
 #[derive(Debug, Default, Deserialize, Document)]
 pub struct EuphServer {
+    /// Base URL that cove connects to for this server.
+    ///
+    /// Useful for self-hosted instances running on a custom port or under a
+    /// sub-path, for example `"https://localhost:8080/heim/"`.
+    #[document(default = "`\"https://<domain>/\"`")]
+    pub url: Option<String>,
+
+    /// Whether to allow connecting to this server without encryption, i.e. via
+    /// an `http://` or `ws://` URL.
+    #[serde(default)]
+    pub allow_insecure_transport: bool,
+
+    /// Additional PEM encoded root certificates to trust when connecting to
+    /// this server, for example for instances using a self-signed certificate.
+    ///
+    /// Relative paths are interpreted relative to the user's home directory.
+    #[serde(default)]
+    pub trusted_root_certs: Vec<PathBuf>,
+
     #[document(metavar = "room")]
     pub rooms: HashMap<String, EuphRoom>,
 }
 
+impl EuphServer {
+    pub fn url(&self, domain: &str) -> String {
+        self.url
+            .clone()
+            .unwrap_or_else(|| format!("https://{domain}/"))
+    }
+
+    pub fn insecure_transport(&self) -> bool {
+        self.url
+            .as_ref()
+            .is_some_and(|url| url.starts_with("http://") || url.starts_with("ws://"))
+    }
+}
+
 #[derive(Debug, Default, Deserialize, Document)]
 pub struct Euph {
+    /// How long messages are kept in the vault.
+    ///
+    /// Messages are deleted on startup and when running `cove gc --prune`.
+    /// Room-specific options take precedence over `euph.retention`.
+    ///
+    /// Deleted messages are not downloaded again. Bookmarks of deleted
+    /// messages are deleted as well.
+    #[serde(default)]
+    #[document(no_default)]
+    pub retention: Retention,
+
     #[document(metavar = "domain")]
     pub servers: HashMap<String, EuphServer>,
 }
@@ -81,6 +81,11 @@ default_bindings! {
         pub fn nick => ["n"];
         pub fn more_messages => ["m"];
         pub fn account => ["A"];
+        pub fn resync => ["ctrl+r"];
+        pub fn pm => ["p"];
+        pub fn moderate => ["M"];
+        pub fn search => ["/"];
+        pub fn bookmarks => ["B"];
     }
 
     pub mod tree_cursor {
@@ -100,6 +105,7 @@ default_bindings! {
         pub fn new_thread => ["t"];
         pub fn fold_tree => [" "];
         pub fn toggle_seen => ["s"];
+        pub fn toggle_bookmark => ["b"];
         pub fn mark_visible_seen => ["S"];
         pub fn mark_older_seen => ["ctrl+s"];
         pub fn info => ["i"];
@@ -288,6 +294,21 @@ pub struct RoomAction {
     /// Manage account.
     #[serde(default = "default::room_action::account")]
     pub account: KeyBinding,
+    /// Redownload recent messages to find edits and deletions.
+    #[serde(default = "default::room_action::resync")]
+    pub resync: KeyBinding,
+    /// Start a pm with the selected session in the nick list.
+    #[serde(default = "default::room_action::pm")]
+    pub pm: KeyBinding,
+    /// Moderate the selected message or session (requires manager permissions).
+    #[serde(default = "default::room_action::moderate")]
+    pub moderate: KeyBinding,
+    /// Search messages in the room.
+    #[serde(default = "default::room_action::search")]
+    pub search: KeyBinding,
+    /// List bookmarked messages of all rooms.
+    #[serde(default = "default::room_action::bookmarks")]
+    pub bookmarks: KeyBinding,
 }
 
 #[derive(Debug, Default, Deserialize, Document)]
@@ -345,6 +366,9 @@ pub struct TreeAction {
     /// Toggle current message's seen status.
     #[serde(default = "default::tree_action::toggle_seen")]
     pub toggle_seen: KeyBinding,
+    /// Bookmark current message or remove its bookmark.
+    #[serde(default = "default::tree_action::toggle_bookmark")]
+    pub toggle_bookmark: KeyBinding,
     /// Mark all visible messages as seen.
     #[serde(default = "default::tree_action::mark_visible_seen")]
     pub mark_visible_seen: KeyBinding,
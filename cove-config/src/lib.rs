@@ -19,6 +19,12 @@ pub enum Error {
     Io(#[from] io::Error),
     #[error("failed to parse config file")]
     Toml(#[from] toml::de::Error),
+    #[error(
+        "url for {0} is insecure, set euph.servers.<domain>.allow_insecure_transport to use it"
+    )]
+    InsecureTransport(String),
+    #[error("backup.keep must be at least 1")]
+    InvalidBackupKeep,
 }
 
 #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Document)]
@@ -29,6 +35,42 @@ pub enum WidthEstimationMethod {
     Unicode,
 }
 
+fn default_backup_keep() -> u64 {
+    5
+}
+
+#[derive(Debug, Deserialize, Document)]
+pub struct Backup {
+    /// If set, cove regularly backs up the vault while it is running, waiting
+    /// this many minutes between backups.
+    ///
+    /// See also the `cove backup` command.
+    pub interval_minutes: Option<u64>,
+
+    /// The directory that regular backups are stored in.
+    ///
+    /// Relative paths are interpreted relative to the user's home directory.
+    #[document(default = "`backups` directory in the data dir")]
+    pub dir: Option<PathBuf>,
+
+    /// How many regular backups to keep. Once there are more backups, the
+    /// oldest ones are deleted.
+    ///
+    /// Must be at least 1.
+    #[serde(default = "default_backup_keep")]
+    pub keep: u64,
+}
+
+impl Default for Backup {
+    fn default() -> Self {
+        Self {
+            interval_minutes: None,
+            dir: None,
+            keep: default_backup_keep(),
+        }
+    }
+}
+
 #[derive(Debug, Default, Deserialize, Document)]
 pub struct Config {
     /// The directory that cove stores its data in when not running in ephemeral
@@ -104,6 +146,13 @@ pub struct Config {
     #[serde(default)]
     pub bell_on_mention: bool,
 
+    /// Whether to hide deleted messages instead of displaying a placeholder.
+    ///
+    /// Deleted messages that have replies are always displayed as a
+    /// placeholder so the replies stay in their thread.
+    #[serde(default)]
+    pub hide_deleted_messages: bool,
+
     /// Time zone that chat timestamps should be displayed in.
     ///
     /// This option can either be the string `"localtime"`, a [POSIX TZ string],
@@ -125,6 +174,10 @@ pub struct Config {
     #[document(default = "`$TZ` or local system time zone")]
     pub time_zone: Option<String>,
 
+    #[serde(default)]
+    #[document(no_default)]
+    pub backup: Backup,
+
     #[serde(default)]
     #[document(no_default)]
     pub euph: Euph,
@@ -136,11 +189,27 @@ pub struct Config {
 
 impl Config {
     pub fn load(path: &Path) -> Result<Self, Error> {
-        Ok(match fs::read_to_string(path) {
+        let config: Self = match fs::read_to_string(path) {
             Ok(content) => toml::from_str(&content)?,
             Err(err) if err.kind() == ErrorKind::NotFound => Self::default(),
             Err(err) => Err(err)?,
-        })
+        };
+
+        for (domain, server) in &config.euph.servers {
+            if server.insecure_transport() && !server.allow_insecure_transport {
+                return Err(Error::InsecureTransport(domain.clone()));
+            }
+        }
+
+        if config.backup.keep == 0 {
+            return Err(Error::InvalidBackupKeep);
+        }
+
+        Ok(config)
+    }
+
+    pub fn euph_server(&self, domain: &str) -> Option<&EuphServer> {
+        self.euph.servers.get(domain)
     }
 
     pub fn euph_room(&self, domain: &str, name: &str) -> EuphRoom {
@@ -152,6 +221,12 @@ impl Config {
         EuphRoom::default()
     }
 
+    pub fn euph_retention(&self, domain: &str, name: &str) -> Retention {
+        self.euph_room(domain, name)
+            .retention
+            .or(self.euph.retention)
+    }
+
     pub fn time_zone_ref(&self) -> Option<&str> {
         self.time_zone.as_ref().map(|s| s as &str)
     }
@@ -0,0 +1,75 @@
+//! Back up the vault, both on demand and regularly while the client runs.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use jiff::Timestamp;
+use log::{error, info};
+
+use crate::vault::Vault;
+
+const BACKUP_PREFIX: &str = "vault-";
+const BACKUP_SUFFIX: &str = ".db";
+
+#[derive(Debug, clap::Parser)]
+pub struct Args {
+    /// Path of the backup file.
+    ///
+    /// Existing files are overwritten.
+    path: PathBuf,
+}
+
+pub async fn backup(vault: &Vault, args: Args) -> anyhow::Result<()> {
+    eprintln!("Backing up vault to {}", args.path.to_string_lossy());
+    vault.backup(args.path).await?;
+    Ok(())
+}
+
+/// Paths of all regular backups in a directory, sorted from oldest to newest.
+fn existing_backups(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut backups = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with(BACKUP_PREFIX) && name.ends_with(BACKUP_SUFFIX) {
+            backups.push(entry.path());
+        }
+    }
+
+    // The timestamps in the file names sort chronologically
+    backups.sort_unstable();
+    Ok(backups)
+}
+
+async fn backup_and_rotate(vault: &Vault, dir: &Path, keep: usize) -> anyhow::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let time = Timestamp::now().strftime("%Y%m%dT%H%M%SZ");
+    let path = dir.join(format!("{BACKUP_PREFIX}{time}{BACKUP_SUFFIX}"));
+    info!("Backing up vault to {}", path.to_string_lossy());
+    vault.backup(path).await?;
+
+    let backups = existing_backups(dir)?;
+    let superfluous = backups.len().saturating_sub(keep);
+    for path in &backups[..superfluous] {
+        info!("Deleting old backup {}", path.to_string_lossy());
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+/// Back up the vault to `dir` every `interval`, keeping only the newest `keep`
+/// backups.
+pub async fn regularly_backup(vault: Vault, dir: PathBuf, interval: Duration, keep: usize) {
+    loop {
+        tokio::time::sleep(interval).await;
+        if let Err(err) = backup_and_rotate(&vault, &dir, keep).await {
+            error!("Failed to back up vault: {err}");
+        }
+    }
+}
@@ -1,9 +1,15 @@
 pub use highlight::*;
+pub use resync::*;
+pub use retention::*;
 pub use room::*;
+pub use server::*;
 pub use small_message::*;
 pub use util::*;
 
 mod highlight;
+mod resync;
+mod retention;
 mod room;
+mod server;
 mod small_message;
 mod util;
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use euphoxide::{
+    api::{Log, Message, Time, UserId},
+    client::ClientConnHandle,
+};
+use log::debug;
+
+use crate::vault::EuphRoomVault;
+
+use super::Error;
+
+/// Amount of messages requested per window.
+const WINDOW_SIZE: usize = 1000;
+
+fn changed(known: &Message, msg: &Message) -> bool {
+    known.content != msg.content
+        || known.edited.map(|t| t.0) != msg.edited.map(|t| t.0)
+        || known.deleted.map(|t| t.0) != msg.deleted.map(|t| t.0)
+}
+
+/// Re-request the newest `windows` windows of the room log and apply any
+/// changes to the vault.
+///
+/// Messages whose content, edit or deletion time differ from the stored
+/// version are updated. Stored messages that lie between two messages returned
+/// by the server but are no longer returned themselves are marked as deleted.
+/// Messages at the edges of a window are never marked as deleted since the
+/// server may simply not have returned them.
+///
+/// Returns the amount of messages that changed.
+pub async fn resync_logs(
+    vault: &EuphRoomVault,
+    conn: &ClientConnHandle,
+    own_user_id: Option<UserId>,
+    windows: usize,
+) -> Result<usize, Error> {
+    // Pruned messages are not added to the vault again, so there is no point
+    // in redownloading them.
+    let pruned_before = vault.pruned_before().await?;
+
+    let mut changes = 0;
+    let mut before = None;
+
+    for _ in 0..windows {
+        // The reply is also handled by the regular packet handling code, which
+        // may update the vault before we get to see the reply. To still be
+        // able to tell what changed, the stored messages are retrieved before
+        // the request is sent.
+        let known = vault.chunk_before(before, WINDOW_SIZE).await?;
+
+        debug!("{:?}: resyncing logs before {before:?}", vault.room());
+        let reply = conn
+            .send(Log {
+                n: WINDOW_SIZE,
+                before,
+            })?
+            .await?;
+        let (Some(first), Some(last)) = (reply.log.first(), reply.log.last()) else {
+            break; // We've reached the beginning of the room history
+        };
+        let first_id = first.id;
+        let last_id = last.id;
+
+        let mut known = known
+            .into_iter()
+            .filter(|msg| first_id <= msg.id && msg.id <= last_id)
+            .map(|msg| (msg.id, msg))
+            .collect::<HashMap<_, _>>();
+
+        for msg in &reply.log {
+            if let Some(known) = known.remove(&msg.id) {
+                if changed(&known, msg) {
+                    changes += 1;
+                }
+            }
+        }
+
+        // Whatever is left is no longer visible on the server. Since the
+        // window's first and last message were returned, everything left lies
+        // strictly between returned messages.
+        let deleted = known
+            .into_values()
+            .filter(|msg| msg.deleted.is_none())
+            .map(|msg| msg.id)
+            .collect::<Vec<_>>();
+        changes += deleted.len();
+
+        vault
+            .add_msgs(reply.log, before, own_user_id.clone())
+            .await?;
+        vault.mark_deleted(deleted, Time::now()).await?;
+
+        if pruned_before.is_some_and(|id| first_id <= id) {
+            break; // Older messages were pruned
+        }
+        before = Some(first_id);
+    }
+
+    Ok(changes)
+}
@@ -0,0 +1,37 @@
+use cove_config::Config;
+use euphoxide::api::Time;
+
+use crate::vault::EuphVault;
+
+const SECONDS_PER_DAY: i64 = 60 * 60 * 24;
+
+/// Delete messages from the vault according to the `retention` config options
+/// of each room.
+///
+/// Returns the amount of messages that were deleted.
+pub async fn prune_rooms(
+    config: &Config,
+    vault: &EuphVault,
+) -> Result<usize, vault::tokio::Error<rusqlite::Error>> {
+    let now = Time::now();
+    let mut pruned = 0;
+
+    for room in vault.rooms().await? {
+        let retention = config.euph_retention(&room.domain, &room.name);
+        if retention.is_unlimited() {
+            continue;
+        }
+
+        let older_than = retention.max_age_days.map(|days| {
+            let days = i64::try_from(days).unwrap_or(i64::MAX);
+            Time(now.0.saturating_sub(days.saturating_mul(SECONDS_PER_DAY)))
+        });
+        let keep_newest = retention
+            .max_count
+            .map(|count| usize::try_from(count).unwrap_or(usize::MAX));
+
+        pruned += vault.room(room).prune(older_than, keep_newest).await?;
+    }
+
+    Ok(pruned)
+}
@@ -1,9 +1,16 @@
-use std::{convert::Infallible, fmt, time::Duration};
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use euphoxide::{
     api::{
-        Auth, AuthOption, Data, Log, Login, Logout, MessageId, Nick, ParsedPacket, Send, SendEvent,
-        SendReply, Time, UserId,
+        Auth, AuthOption, Ban, Data, EditMessage, GetMessage, GetMessageReply, GrantAccess,
+        GrantManager, Log, Login, Logout, MessageId, Nick, ParsedPacket, PmInitiate, RevokeAccess,
+        RevokeManager, Send, SendEvent, SendReply, Snowflake, Time, Unban, UserId,
     },
     client::{self, ClientConnHandle, Joined},
 };
@@ -16,7 +23,17 @@ use tokio::{
 
 use crate::{macros::logging_unwrap, vault::EuphRoomVault};
 
+use super::resync_logs;
+
 const LOG_INTERVAL: Duration = Duration::from_secs(10);
+const UNTRUNCATE_INTERVAL: Duration = Duration::from_secs(1);
+/// Amount of messages that may fail to untruncate before cove stops trying to
+/// untruncate messages in a room.
+const MAX_UNTRUNCATE_FAILURES: usize = 100;
+
+/// Amount of log windows that are resynced after joining a room for the first
+/// time in a session, or when a resync is requested.
+const RESYNC_WINDOWS: usize = 5;
 
 #[expect(clippy::large_enum_variant)]
 pub enum State {
@@ -69,6 +86,8 @@ pub enum Error {
     NotConnected,
     #[error("{0}")]
     Euphoxide(#[from] euphoxide::Error),
+    #[error("{0}")]
+    Vault(#[from] vault::tokio::Error<rusqlite::Error>),
 }
 
 #[derive(Debug)]
@@ -86,6 +105,22 @@ pub struct Room {
     /// `Some` while `Self::regularly_request_logs` is running. Set to `None` to
     /// drop the sender and stop the task.
     log_request_canary: Option<oneshot::Sender<Infallible>>,
+
+    /// `Some` while a resync task is running. Set to `None` to drop the sender
+    /// and stop the task.
+    resync_canary: Option<oneshot::Sender<Infallible>>,
+
+    /// Whether the room was already resynced automatically after joining.
+    /// Since resyncing redownloads many messages, this only happens once per
+    /// session and not after every reconnect.
+    resynced: bool,
+
+    /// `Some` while `Self::regularly_untruncate` is running. Set to `None` to
+    /// drop the sender and stop the task.
+    untruncate_canary: Option<oneshot::Sender<Infallible>>,
+
+    /// Messages currently visible to the user. These are untruncated first.
+    visible_msgs: Arc<Mutex<Vec<MessageId>>>,
 }
 
 impl Room {
@@ -101,6 +136,10 @@ impl Room {
             state: State::Disconnected,
             last_msg_id: None,
             log_request_canary: None,
+            resync_canary: None,
+            resynced: false,
+            untruncate_canary: None,
+            visible_msgs: Arc::new(Mutex::new(vec![])),
             vault,
         }
     }
@@ -130,22 +169,10 @@ impl Room {
                 // Juuust to make sure
                 self.last_msg_id = None;
                 self.log_request_canary = None;
+                self.resync_canary = None;
+                self.untruncate_canary = None;
             }
             ClientEvent::Connected { conn, state } => {
-                if !self.ephemeral {
-                    let (tx, rx) = oneshot::channel();
-                    self.log_request_canary = Some(tx);
-                    let vault_clone = self.vault.clone();
-                    let conn_clone = conn.clone();
-                    debug!("{}: spawning log request task", self.client.config().room);
-                    tokio::task::spawn(async move {
-                        select! {
-                            _ = rx => {},
-                            _ = Self::regularly_request_logs(vault_clone, conn_clone) => {},
-                        }
-                    });
-                }
-
                 self.state = State::Connected { conn, state };
 
                 let cookies = &*self.client.config().server.cookies;
@@ -166,6 +193,8 @@ impl Room {
                 self.state = State::Disconnected;
                 self.last_msg_id = None;
                 self.log_request_canary = None;
+                self.resync_canary = None;
+                self.untruncate_canary = None;
             }
             ClientEvent::Stopped => {
                 self.state = State::Stopped;
@@ -180,48 +209,165 @@ impl Room {
         // sort of "repair logs" mode that can be started via some key binding.
         // For now, this is just a list of ideas.
         //
-        // Download room history until there are no more gaps between now and
-        // the first known message.
-        //
-        // Download room history until reaching the beginning of the room's
-        // history.
-        //
         // Check if the last known message still exists on the server. If it
         // doesn't, do a binary search to find the server's last message and
         // delete all older messages.
         //
-        // Untruncate messages in the history, as well as new messages.
-        //
         // Try to retrieve messages that are not in the room log by retrieving
         // them by id.
         //
-        // Redownload messages that are already known to find any edits and
-        // deletions that happened while the client was offline.
-        //
         // Delete messages marked as deleted as well as all their children.
 
+        // Every gap gets one request per round, newest gap first, so older gaps
+        // are filled alongside the newer ones instead of only after them. Since
+        // gaps are stored in the vault, backfilling continues where it left off
+        // after reconnecting. Before the first snapshot of a room is stored,
+        // there are no gaps to fill.
         loop {
-            tokio::time::sleep(LOG_INTERVAL).await;
-            if !Self::request_logs(&vault, &conn).await {
-                break;
+            let gaps = logging_unwrap!(vault.gaps().await);
+            if gaps.is_empty() {
+                break; // The room history is complete
+            }
+
+            let amount = gaps.len();
+            for (i, (_, before)) in gaps.into_iter().rev().enumerate() {
+                tokio::time::sleep(LOG_INTERVAL).await;
+                debug!(
+                    "{:?}: requesting logs before {before:?} (gap {} of {amount})",
+                    vault.room(),
+                    i + 1
+                );
+                if !Self::request_logs_before(&conn, before) {
+                    return;
+                }
             }
         }
     }
 
+    /// Request logs to fill the newest gap in the room history.
+    ///
+    /// Returns `false` if there are no gaps left or the request failed.
     async fn request_logs(vault: &EuphRoomVault, conn: &ClientConnHandle) -> bool {
-        let before = match logging_unwrap!(vault.last_span().await) {
-            Some((None, _)) => return false, // Already at top of room history
-            Some((Some(before), _)) => Some(before),
-            None => None,
+        let gaps = logging_unwrap!(vault.gaps().await);
+        let Some(&(_, before)) = gaps.last() else {
+            return false; // No gaps left, the room history is complete
         };
 
-        debug!("{:?}: requesting logs", vault.room());
+        debug!(
+            "{:?}: requesting logs before {before:?} ({} gaps left)",
+            vault.room(),
+            gaps.len()
+        );
+        Self::request_logs_before(conn, before)
+    }
 
-        conn.send_only(Log { n: 1000, before }).is_ok()
+    fn request_logs_before(conn: &ClientConnHandle, before: MessageId) -> bool {
+        conn.send_only(Log {
+            n: 1000,
+            before: Some(before),
+        })
+        .is_ok()
         // The code handling incoming events and replies also handles
         // `LogReply`s, so we don't need to do anything special here.
     }
 
+    async fn regularly_untruncate(
+        vault: EuphRoomVault,
+        conn: ClientConnHandle,
+        visible_msgs: Arc<Mutex<Vec<MessageId>>>,
+    ) {
+        // Messages that were still truncated after we requested them. They are
+        // not requested again.
+        let mut failed = HashSet::new();
+
+        loop {
+            tokio::time::sleep(UNTRUNCATE_INTERVAL).await;
+
+            let prioritized = visible_msgs.lock().unwrap().clone();
+            let amount = failed.len() + 1;
+            let ids = logging_unwrap!(vault.truncated_msg_ids(prioritized, amount).await);
+            let Some(id) = ids.into_iter().find(|id| !failed.contains(id)) else {
+                continue;
+            };
+
+            debug!("{:?}: untruncating message {id:?}", vault.room());
+            let Ok(reply) = conn.send(GetMessage { id }) else {
+                break;
+            };
+
+            // The code handling incoming events and replies also handles
+            // `GetMessageReply`s, so we only need to check whether it worked.
+            let untruncated = matches!(reply.await, Ok(GetMessageReply(msg)) if !msg.truncated);
+            if !untruncated {
+                failed.insert(id);
+                if failed.len() >= MAX_UNTRUNCATE_FAILURES {
+                    warn!("{:?}: too many messages failed to untruncate", vault.room());
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Start a resync task, replacing any resync task that is already running.
+    fn spawn_resync(&mut self, windows: usize) -> Result<(), Error> {
+        let conn_clone = self.conn()?.clone();
+        let vault_clone = self.vault.clone();
+        let own_user_id = self.own_user_id();
+
+        let (tx, rx) = oneshot::channel();
+        self.resync_canary = Some(tx);
+        debug!("{}: spawning resync task", self.client.config().room);
+        tokio::task::spawn(async move {
+            select! {
+                _ = rx => {},
+                result = resync_logs(&vault_clone, &conn_clone, own_user_id, windows) => {
+                    let room = vault_clone.room();
+                    match result {
+                        Ok(changes) => info!("{room:?}: resync found {changes} changed messages"),
+                        Err(err) => warn!("{room:?}: resync failed: {err}"),
+                    }
+                },
+            }
+        });
+
+        Ok(())
+    }
+
+    fn spawn_request_logs(&mut self) {
+        let Ok(conn_clone) = self.conn().cloned() else {
+            return;
+        };
+        let vault_clone = self.vault.clone();
+
+        let (tx, rx) = oneshot::channel();
+        self.log_request_canary = Some(tx);
+        debug!("{}: spawning log request task", self.client.config().room);
+        tokio::task::spawn(async move {
+            select! {
+                _ = rx => {},
+                _ = Self::regularly_request_logs(vault_clone, conn_clone) => {},
+            }
+        });
+    }
+
+    fn spawn_untruncate(&mut self) {
+        let Ok(conn_clone) = self.conn().cloned() else {
+            return;
+        };
+        let vault_clone = self.vault.clone();
+        let visible_msgs_clone = self.visible_msgs.clone();
+
+        let (tx, rx) = oneshot::channel();
+        self.untruncate_canary = Some(tx);
+        debug!("{}: spawning untruncate task", self.client.config().room);
+        tokio::task::spawn(async move {
+            select! {
+                _ = rx => {},
+                _ = Self::regularly_untruncate(vault_clone, conn_clone, visible_msgs_clone) => {},
+            }
+        });
+    }
+
     fn own_user_id(&self) -> Option<UserId> {
         if let State::Connected { state, .. } = &self.state {
             Some(match state {
@@ -253,15 +399,19 @@ impl Room {
             Data::NickEvent(d) => {
                 debug!("{room_name}: {:?} renamed to {:?}", d.from, d.to);
             }
-            Data::EditMessageEvent(_) => {
+            Data::EditMessageEvent(d) => {
                 info!("{room_name}: a message was edited");
+                logging_unwrap!(
+                    self.vault
+                        .edit_msg(d.edit_id, Box::new(d.message.clone()))
+                        .await
+                );
             }
             Data::PartEvent(d) => {
                 debug!("{room_name}: {:?} left", d.0.name);
             }
             Data::PingEvent(_) => {}
             Data::PmInitiateEvent(d) => {
-                // TODO Show info popup and automatically join PM room
                 info!(
                     "{room_name}: {:?} initiated a pm from &{}",
                     d.from_nick, d.from_room
@@ -287,6 +437,24 @@ impl Room {
                         .add_msgs(d.log.clone(), None, self.own_user_id())
                         .await
                 );
+
+                // Gaps can only be found once the snapshot is in the vault
+                if !self.ephemeral {
+                    self.spawn_request_logs();
+                }
+
+                // Catch any edits and deletions that happened while we were
+                // offline
+                if !self.ephemeral && !self.resynced && self.spawn_resync(RESYNC_WINDOWS).is_ok() {
+                    self.resynced = true;
+                }
+
+                if !self.ephemeral {
+                    self.spawn_untruncate();
+                }
+            }
+            Data::GetMessageReply(GetMessageReply(msg)) => {
+                logging_unwrap!(self.vault.update_msg(Box::new(msg.clone())).await);
             }
             Data::LogReply(d) => {
                 logging_unwrap!(
@@ -314,6 +482,20 @@ impl Room {
         Ok(())
     }
 
+    pub fn resync(&mut self) -> Result<(), Error> {
+        self.spawn_resync(RESYNC_WINDOWS)
+    }
+
+    /// Set the messages that should be untruncated first.
+    pub fn set_visible_msgs(&self, ids: Vec<MessageId>) {
+        *self.visible_msgs.lock().unwrap() = ids;
+    }
+
+    pub fn pm_initiate(&self, user_id: UserId) -> Result<(), Error> {
+        self.conn()?.send_only(PmInitiate { user_id })?;
+        Ok(())
+    }
+
     pub fn nick(&self, name: String) -> Result<(), Error> {
         self.conn()?.send_only(Nick { name })?;
         Ok(())
@@ -334,6 +516,60 @@ impl Room {
         Ok(rx)
     }
 
+    pub fn delete_msg(
+        &self,
+        id: MessageId,
+        parent: Option<MessageId>,
+        previous_edit_id: Option<Snowflake>,
+    ) -> Result<(), Error> {
+        self.conn()?.send_only(EditMessage {
+            id,
+            // Messages that were never edited have a previous edit id of 0
+            previous_edit_id: previous_edit_id.unwrap_or(Snowflake(0)),
+            parent,
+            content: None,
+            delete: true,
+            announce: true,
+        })?;
+        Ok(())
+    }
+
+    pub fn ban(&self, id: UserId) -> Result<(), Error> {
+        self.conn()?.send_only(Ban { id, seconds: None })?;
+        Ok(())
+    }
+
+    pub fn unban(&self, id: UserId) -> Result<(), Error> {
+        self.conn()?.send_only(Unban { id })?;
+        Ok(())
+    }
+
+    pub fn grant_access(&self, account_id: Snowflake) -> Result<(), Error> {
+        self.conn()?.send_only(GrantAccess {
+            account_id: Some(account_id),
+            passcode: None,
+        })?;
+        Ok(())
+    }
+
+    pub fn revoke_access(&self, account_id: Snowflake) -> Result<(), Error> {
+        self.conn()?.send_only(RevokeAccess {
+            account_id: Some(account_id),
+            passcode: None,
+        })?;
+        Ok(())
+    }
+
+    pub fn grant_manager(&self, account_id: Snowflake) -> Result<(), Error> {
+        self.conn()?.send_only(GrantManager { account_id })?;
+        Ok(())
+    }
+
+    pub fn revoke_manager(&self, account_id: Snowflake) -> Result<(), Error> {
+        self.conn()?.send_only(RevokeManager { account_id })?;
+        Ok(())
+    }
+
     pub fn login(&self, email: String, password: String) -> Result<(), Error> {
         self.conn()?.send_only(Login {
             namespace: "email".to_string(),
@@ -0,0 +1,71 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use cookie::CookieJar;
+use euphoxide_client::ServerConfig;
+use log::error;
+use rustls::{
+    RootCertStore,
+    pki_types::{CertificateDer, pem::PemObject},
+};
+
+fn root_cert_store(domain: &str, paths: &[PathBuf]) -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+
+    // Additional certificates extend the platform's certificates instead of
+    // replacing them.
+    let native = rustls_native_certs::load_native_certs();
+    roots.add_parsable_certificates(native.certs);
+
+    for path in paths {
+        let certs = match CertificateDer::pem_file_iter(path) {
+            Ok(certs) => certs,
+            Err(err) => {
+                error!("{domain}: failed to read certificates from {path:?}: {err}");
+                continue;
+            }
+        };
+
+        for cert in certs {
+            let result = match cert {
+                Ok(cert) => roots.add(cert).map_err(|err| err.to_string()),
+                Err(err) => Err(err.to_string()),
+            };
+            if let Err(err) = result {
+                error!("{domain}: invalid certificate in {path:?}: {err}");
+            }
+        }
+    }
+
+    roots
+}
+
+/// Create the connection config for a server, taking into account the
+/// `euph.servers.<domain>` config options.
+pub fn server_config(
+    config: &cove_config::Config,
+    domain: &str,
+    cookies: CookieJar,
+) -> ServerConfig {
+    let mut server_config = ServerConfig::default();
+    server_config.cookies = Arc::new(Mutex::new(cookies));
+    server_config.client.url = format!("https://{domain}/");
+
+    let Some(server) = config.euph_server(domain) else {
+        return server_config;
+    };
+
+    server_config.client.url = server.url(domain);
+
+    if !server.trusted_root_certs.is_empty() {
+        let roots = root_cert_store(domain, &server.trusted_root_certs);
+        let tls_config = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        server_config.client.tls_config = Some(Arc::new(tls_config));
+    }
+
+    server_config
+}
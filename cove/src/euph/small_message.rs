@@ -16,6 +16,7 @@ pub struct SmallMessage {
     pub nick: String,
     pub content: String,
     pub seen: bool,
+    pub deleted: bool,
 }
 
 fn as_me(content: &str) -> Option<&str> {
@@ -37,6 +38,10 @@ fn styled_nick_me(nick: &str) -> Styled {
     Styled::new("*", style).and_then(super::style_nick(nick, style))
 }
 
+fn styled_deleted() -> Styled {
+    Styled::new("[deleted]", Style::new().dark_grey().italic())
+}
+
 fn styled_content(content: &str) -> Styled {
     super::highlight(content.trim(), Style::new(), false)
 }
@@ -85,6 +90,9 @@ impl ChatMsg for SmallMessage {
     }
 
     fn styled(&self) -> (Styled, Styled) {
+        if self.deleted {
+            return (styled_nick(&self.nick), styled_deleted());
+        }
         Self::pseudo(&self.nick, &self.content)
     }
 
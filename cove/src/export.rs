@@ -1,14 +1,27 @@
 //! Export logs from the vault to plain text files.
 
 use std::{
-    fs::File,
+    fs::{self, File, OpenOptions},
     io::{self, BufWriter, Write},
+    path::Path,
 };
 
-use crate::vault::{EuphRoomVault, EuphVault, RoomIdentifier};
+use anyhow::Context;
+use euphoxide::api::{Message, MessageId, Snowflake, Time};
+use jiff::{Timestamp, tz::TimeZone};
 
-mod json;
-mod text;
+use crate::{
+    util,
+    vault::{EuphRoomVault, EuphVault, RoomIdentifier},
+};
+
+use self::text::Timestamps;
+
+pub mod bookmarks;
+pub mod chronological;
+pub mod html;
+pub mod json;
+pub mod text;
 
 #[derive(Debug, Clone, Copy, clap::ValueEnum)]
 pub enum Format {
@@ -19,6 +32,11 @@ pub enum Format {
     /// Message objects in the same format as the euphoria API uses, one per
     /// line (https://jsonlines.org/).
     JsonLines,
+    /// Self-contained web page with collapsible threads.
+    Html,
+    /// One line per message in chronological order, with message and parent
+    /// ids.
+    Chronological,
 }
 
 impl Format {
@@ -27,6 +45,8 @@ impl Format {
             Self::Text => "text",
             Self::Json => "json",
             Self::JsonLines => "json lines",
+            Self::Html => "html",
+            Self::Chronological => "chronological",
         }
     }
 
@@ -35,6 +55,8 @@ impl Format {
             Self::Text => "txt",
             Self::Json => "json",
             Self::JsonLines => "jsonl",
+            Self::Html => "html",
+            Self::Chronological => "log",
         }
     }
 }
@@ -47,6 +69,13 @@ pub struct Args {
     #[arg(long, short)]
     all: bool,
 
+    /// Export the bookmarked messages of all rooms instead of room logs.
+    ///
+    /// All bookmarks are written to a single output file, with `%r` expanding
+    /// to `bookmarks`.
+    #[arg(long, short)]
+    bookmarks: bool,
+
     /// Domain to resolve the room names with.
     #[arg(long, short, default_value = "euphoria.leet.nu")]
     domain: String,
@@ -55,6 +84,56 @@ pub struct Args {
     #[arg(long, short, value_enum, default_value_t = Format::Text)]
     format: Format,
 
+    /// Only export threads with messages sent at or after this time (e. g.
+    /// `2024-01-01T00:00:00Z`).
+    ///
+    /// Threads are exported in their entirety, including older messages.
+    #[arg(long, short)]
+    since: Option<Timestamp>,
+
+    /// Only export threads with messages sent before this time (e. g.
+    /// `2024-01-01T00:00:00Z`).
+    ///
+    /// Threads are exported in their entirety, including newer messages.
+    #[arg(long)]
+    until: Option<Timestamp>,
+
+    /// Only export the subtree starting at this message id (e. g.
+    /// `06a7kgjx6gqgw`).
+    ///
+    /// Requires exactly one room.
+    #[arg(long, short, value_parser = parse_msg_id, conflicts_with_all = ["all", "since", "until"])]
+    thread: Option<MessageId>,
+
+    /// Format of timestamps in the text, html and chronological formats.
+    ///
+    /// Uses `strftime` syntax, e. g. `%Y-%m-%d %H:%M:%S`.
+    #[arg(long, default_value_t = Into::into(text::TIME_FORMAT))]
+    time_format: String,
+
+    /// Time zone to display timestamps in (e. g. `Europe/Berlin` or `UTC`).
+    ///
+    /// Uses the same format as the `time_zone` config option, which is used by
+    /// default.
+    #[arg(long)]
+    time_zone: Option<String>,
+
+    /// Only append messages newer than those of the previous export to the
+    /// same file.
+    ///
+    /// The id of the last exported message is remembered in a file next to the
+    /// output file with the additional extension `.cove-export`.
+    ///
+    /// With the text format, threads are only exported once they are closed,
+    /// i. e. once their newest message is at least a day old and all gaps
+    /// before them have been filled. Messages that are added to a thread after
+    /// it was exported, or that are older than the last export, are not
+    /// exported.
+    ///
+    /// Only supported by the text, json lines and chronological formats.
+    #[arg(long, short, conflicts_with_all = ["thread", "since", "until"])]
+    incremental: bool,
+
     /// Location of the output file
     ///
     /// May include the following placeholders:
@@ -74,24 +153,221 @@ pub struct Args {
     out: String,
 }
 
+fn parse_msg_id(s: &str) -> Result<MessageId, String> {
+    u64::from_str_radix(s, 36)
+        .map(|id| MessageId(Snowflake(id)))
+        .map_err(|err| format!("invalid message id: {err}"))
+}
+
+const CHUNK_SIZE: usize = 10000;
+
+/// The trees of a room that are exported.
+#[derive(Debug, Default)]
+pub struct Selection {
+    /// Ids of the roots in ascending order, or `None` if all trees are
+    /// selected.
+    roots: Option<Vec<MessageId>>,
+    /// Only trees and messages newer than this id are selected.
+    after: Option<MessageId>,
+}
+
+impl Selection {
+    async fn from_args(
+        vault: &EuphRoomVault,
+        args: &Args,
+        after: Option<MessageId>,
+    ) -> anyhow::Result<Self> {
+        if after.is_some() {
+            return Ok(Self { roots: None, after });
+        }
+
+        if let Some(id) = args.thread {
+            if vault.msg(id).await?.is_none() {
+                anyhow::bail!("thread not found in &{}", vault.room().name);
+            }
+            return Ok(Self {
+                roots: Some(vec![id]),
+                after: None,
+            });
+        }
+
+        if args.since.is_none() && args.until.is_none() {
+            return Ok(Self::default());
+        }
+
+        let since = args.since.map(|t| Time(t.as_second()));
+        let until = args.until.map(|t| Time(t.as_second()));
+        let roots = vault.active_root_ids(since, until).await?;
+        Ok(Self {
+            roots: Some(roots),
+            after: None,
+        })
+    }
+
+    pub async fn first_root_id(&self, vault: &EuphRoomVault) -> anyhow::Result<Option<MessageId>> {
+        match (&self.roots, self.after) {
+            (Some(roots), _) => Ok(roots.first().copied()),
+            (None, Some(after)) => Ok(vault.next_root_id(after).await?),
+            (None, None) => Ok(vault.first_root_id().await?),
+        }
+    }
+
+    pub async fn next_root_id(
+        &self,
+        vault: &EuphRoomVault,
+        root_id: MessageId,
+    ) -> anyhow::Result<Option<MessageId>> {
+        match &self.roots {
+            Some(roots) => Ok(match roots.binary_search(&root_id) {
+                Ok(i) => roots.get(i + 1).copied(),
+                Err(_) => None,
+            }),
+            None => Ok(vault.next_root_id(root_id).await?),
+        }
+    }
+
+    /// Whether [`Self::chunks`] returns messages in chronological order across
+    /// chunks.
+    pub fn is_ordered(&self) -> bool {
+        self.roots.as_ref().is_none_or(|roots| roots.len() <= 1)
+    }
+
+    pub fn chunks<'a>(&'a self, vault: &'a EuphRoomVault) -> Chunks<'a> {
+        Chunks {
+            vault,
+            roots: self.roots.as_ref().map(|roots| roots.iter()),
+            last_msg_id: self.after,
+        }
+    }
+}
+
+/// Messages of a [`Selection`] in chunks.
+///
+/// Within each chunk, messages are ordered oldest first.
+pub struct Chunks<'a> {
+    vault: &'a EuphRoomVault,
+    roots: Option<std::slice::Iter<'a, MessageId>>,
+    last_msg_id: Option<MessageId>,
+}
+
+impl Chunks<'_> {
+    pub async fn next(&mut self) -> anyhow::Result<Option<Vec<Message>>> {
+        if let Some(roots) = &mut self.roots {
+            return match roots.next() {
+                Some(root_id) => Ok(Some(self.vault.tree_msgs(*root_id).await?)),
+                None => Ok(None),
+            };
+        }
+
+        let messages = self.vault.chunk_after(self.last_msg_id, CHUNK_SIZE).await?;
+        self.last_msg_id = Some(match messages.last() {
+            Some(last_msg) => last_msg.id,
+            None => return Ok(None), // No more messages, export finished
+        });
+        Ok(Some(messages))
+    }
+}
+
+/// Export the messages of a room that come after `after`.
+///
+/// Returns the id of the last exported tree or message, if the format keeps
+/// track of it.
 async fn export_room<W: Write>(
     vault: &EuphRoomVault,
     out: &mut W,
-    format: Format,
+    args: &Args,
+    timestamps: &Timestamps,
+    after: Option<MessageId>,
+) -> anyhow::Result<Option<MessageId>> {
+    let selection = Selection::from_args(vault, args, after).await?;
+    let last_id = match args.format {
+        Format::Text => text::export(vault, out, &selection, timestamps, args.incremental).await?,
+        Format::Json => {
+            json::export(vault, out, &selection).await?;
+            None
+        }
+        Format::JsonLines => json::export_lines(vault, out, &selection).await?,
+        Format::Html => {
+            html::export(vault, out, &selection, timestamps).await?;
+            None
+        }
+        Format::Chronological => chronological::export(vault, out, &selection, timestamps).await?,
+    };
+    Ok(last_id)
+}
+
+fn state_path(out: &str) -> String {
+    format!("{out}.cove-export")
+}
+
+async fn export_room_incremental(
+    vault: &EuphRoomVault,
+    out: &str,
+    args: &Args,
+    timestamps: &Timestamps,
 ) -> anyhow::Result<()> {
-    match format {
-        Format::Text => text::export(vault, out).await?,
-        Format::Json => json::export(vault, out).await?,
-        Format::JsonLines => json::export_lines(vault, out).await?,
+    let state_path = state_path(out);
+
+    // The state belongs to the output file and is meaningless without it.
+    let after = if Path::new(out).is_file() {
+        match fs::read_to_string(&state_path) {
+            Ok(state) => Some(MessageId(Snowflake(state.trim().parse()?))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        }
+    } else {
+        None
+    };
+
+    let file = match after {
+        Some(_) => OpenOptions::new().append(true).open(out)?,
+        None => File::create(out)?,
+    };
+    let mut file = BufWriter::new(file);
+    let last_id = export_room(vault, &mut file, args, timestamps, after).await?;
+    file.flush()?;
+
+    if let Some(id) = last_id.or(after) {
+        fs::write(&state_path, format!("{}\n", id.0.0))?;
     }
+
     Ok(())
 }
 
-pub async fn export(vault: &EuphVault, mut args: Args) -> anyhow::Result<()> {
+/// Export rooms from the vault.
+///
+/// Times are displayed in the time zone `tz` unless a different time zone is
+/// specified in the args.
+pub async fn export(vault: &EuphVault, mut args: Args, tz: TimeZone) -> anyhow::Result<()> {
     if args.out.ends_with('/') {
         args.out.push_str("%r.%e");
     }
 
+    let tz = match &args.time_zone {
+        Some(tz) => util::parse_time_zone(tz).context("failed to load time zone")?,
+        None => tz,
+    };
+    let timestamps =
+        Timestamps::new(args.time_format.clone(), tz).context("invalid time format")?;
+
+    if args.bookmarks {
+        return export_bookmarks(vault, &args, &timestamps).await;
+    }
+
+    if args.incremental {
+        if !matches!(
+            args.format,
+            Format::Text | Format::JsonLines | Format::Chronological
+        ) {
+            anyhow::bail!(
+                "--incremental only supports the text, json lines and chronological formats"
+            );
+        }
+        if args.out == "-" {
+            anyhow::bail!("--incremental can't export to stdout");
+        }
+    }
+
     let rooms = if args.all {
         let mut rooms = vault
             .rooms()
@@ -111,19 +387,31 @@ pub async fn export(vault: &EuphVault, mut args: Args) -> anyhow::Result<()> {
         eprintln!("No rooms to export");
     }
 
+    if args.thread.is_some() && rooms.len() != 1 {
+        anyhow::bail!("--thread requires exactly one room");
+    }
+
     for room in rooms {
         if args.out == "-" {
             eprintln!("Exporting &{room} as {} to stdout", args.format.name());
             let vault = vault.room(RoomIdentifier::new(args.domain.clone(), room));
             let mut stdout = BufWriter::new(io::stdout());
-            export_room(&vault, &mut stdout, args.format).await?;
+            export_room(&vault, &mut stdout, &args, &timestamps, None).await?;
             stdout.flush()?;
+        } else if args.incremental {
+            let out = format_out(&args.out, &room, args.format);
+            eprintln!(
+                "Exporting new messages of &{room} as {} to {out}",
+                args.format.name()
+            );
+            let vault = vault.room(RoomIdentifier::new(args.domain.clone(), room));
+            export_room_incremental(&vault, &out, &args, &timestamps).await?;
         } else {
             let out = format_out(&args.out, &room, args.format);
             eprintln!("Exporting &{room} as {} to {out}", args.format.name());
             let vault = vault.room(RoomIdentifier::new(args.domain.clone(), room));
             let mut file = BufWriter::new(File::create(out)?);
-            export_room(&vault, &mut file, args.format).await?;
+            export_room(&vault, &mut file, &args, &timestamps, None).await?;
             file.flush()?;
         }
     }
@@ -131,6 +419,27 @@ pub async fn export(vault: &EuphVault, mut args: Args) -> anyhow::Result<()> {
     Ok(())
 }
 
+async fn export_bookmarks(
+    vault: &EuphVault,
+    args: &Args,
+    timestamps: &Timestamps,
+) -> anyhow::Result<()> {
+    if args.out == "-" {
+        eprintln!("Exporting bookmarks as {} to stdout", args.format.name());
+        let mut stdout = BufWriter::new(io::stdout());
+        bookmarks::export(vault, &mut stdout, args.format, timestamps).await?;
+        stdout.flush()?;
+    } else {
+        let out = format_out(&args.out, "bookmarks", args.format);
+        eprintln!("Exporting bookmarks as {} to {out}", args.format.name());
+        let mut file = BufWriter::new(File::create(out)?);
+        bookmarks::export(vault, &mut file, args.format, timestamps).await?;
+        file.flush()?;
+    }
+
+    Ok(())
+}
+
 fn format_out(out: &str, room: &str, format: Format) -> String {
     let mut result = String::new();
 
@@ -0,0 +1,78 @@
+use std::io::Write;
+
+use serde_json::json;
+
+use crate::vault::{Bookmark, EuphVault};
+
+use super::{
+    Format,
+    text::{self, Timestamps},
+};
+
+pub async fn export<W: Write>(
+    vault: &EuphVault,
+    out: &mut W,
+    format: Format,
+    timestamps: &Timestamps,
+) -> anyhow::Result<()> {
+    let bookmarks = vault.bookmarks().await?;
+
+    match format {
+        Format::Text => {
+            for bookmark in &bookmarks {
+                write_text(out, timestamps, bookmark)?;
+            }
+        }
+        Format::Json => {
+            let bookmarks = bookmarks.iter().map(to_json).collect::<Vec<_>>();
+            serde_json::to_writer_pretty(&mut *out, &bookmarks)?;
+            writeln!(out)?;
+        }
+        Format::JsonLines => {
+            for bookmark in &bookmarks {
+                serde_json::to_writer(&mut *out, &to_json(bookmark))?;
+                writeln!(out)?;
+            }
+        }
+        Format::Html | Format::Chronological => {
+            anyhow::bail!("bookmarks can't be exported as {}", format.name())
+        }
+    }
+
+    eprintln!("  {} bookmarks in total", bookmarks.len());
+    Ok(())
+}
+
+fn write_text<W: Write>(
+    out: &mut W,
+    timestamps: &Timestamps,
+    bookmark: &Bookmark,
+) -> anyhow::Result<()> {
+    let room = &bookmark.room;
+    match &bookmark.note {
+        Some(note) => writeln!(out, "&{} ({}): {note}", room.name, room.domain)?,
+        None => writeln!(out, "&{} ({})", room.name, room.domain)?,
+    }
+
+    let msg = &bookmark.msg;
+    text::write_msg(
+        out,
+        timestamps,
+        "",
+        msg.time,
+        &msg.sender.name,
+        &msg.content,
+    )?;
+    writeln!(out)?;
+    Ok(())
+}
+
+fn to_json(bookmark: &Bookmark) -> serde_json::Value {
+    json!({
+        "domain": bookmark.room.domain,
+        "room": bookmark.room.name,
+        "note": bookmark.note,
+        "created": bookmark.created.as_timestamp().to_string(),
+        "message": bookmark.msg,
+    })
+}
@@ -0,0 +1,96 @@
+use std::io::Write;
+
+use euphoxide::api::{Message, MessageId};
+
+use crate::vault::EuphRoomVault;
+
+use super::{Selection, text::Timestamps};
+
+/// Export the selected messages of a room, one line per message, oldest first.
+///
+/// Returns the id of the last exported message.
+pub async fn export<W: Write>(
+    vault: &EuphRoomVault,
+    out: &mut W,
+    selection: &Selection,
+    timestamps: &Timestamps,
+) -> anyhow::Result<Option<MessageId>> {
+    let mut last_msg_id = None;
+    let mut total = 0;
+    let mut chunks = selection.chunks(vault);
+
+    if selection.is_ordered() {
+        while let Some(messages) = chunks.next().await? {
+            for message in messages {
+                write_msg(out, timestamps, &message)?;
+                last_msg_id = Some(message.id);
+                total += 1;
+            }
+
+            if total % 100000 == 0 {
+                eprintln!("  {total} messages");
+            }
+        }
+    } else {
+        // Messages of different trees may interleave
+        let mut messages = vec![];
+        while let Some(chunk) = chunks.next().await? {
+            messages.extend(chunk);
+        }
+        messages.sort_unstable_by_key(|msg| msg.id);
+
+        for message in messages {
+            write_msg(out, timestamps, &message)?;
+            last_msg_id = Some(message.id);
+            total += 1;
+        }
+    }
+
+    eprintln!("  {total} messages in total");
+    Ok(last_msg_id)
+}
+
+fn write_msg<W: Write>(out: &mut W, timestamps: &Timestamps, msg: &Message) -> anyhow::Result<()> {
+    let parent = match msg.parent {
+        Some(parent) => parent.0.to_string(),
+        None => "-".to_string(),
+    };
+    writeln!(
+        out,
+        "{} {} {parent} [{}] {}",
+        timestamps.format(msg.time),
+        msg.id.0,
+        escape_nick(&msg.sender.name),
+        escape(&msg.content),
+    )?;
+    Ok(())
+}
+
+/// Escape characters that would break the one-line-per-message format.
+fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for char in text.chars() {
+        match char {
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            _ => result.push(char),
+        }
+    }
+    result
+}
+
+/// Like [`escape`], but also escape the brackets surrounding the nick so the
+/// end of the nick can be found unambiguously.
+fn escape_nick(nick: &str) -> String {
+    let mut result = String::with_capacity(nick.len());
+    for char in escape(nick).chars() {
+        match char {
+            '[' => result.push_str("\\["),
+            ']' => result.push_str("\\]"),
+            _ => result.push(char),
+        }
+    }
+    result
+}
@@ -0,0 +1,234 @@
+use std::io::Write;
+
+use euphoxide::api::{MessageId, Time};
+use linkify::{LinkFinder, LinkKind};
+
+use crate::{
+    euph::{self, SmallMessage, SpanType},
+    store::Tree,
+    vault::EuphRoomVault,
+};
+
+use super::{Selection, text::Timestamps};
+
+const STYLE: &str = "
+body {
+    background-color: #1c1c1c;
+    color: #dddddd;
+    font-family: monospace;
+}
+details, .msg {
+    margin-left: 2ch;
+}
+body > details {
+    margin-left: 0;
+    margin-top: 0.5em;
+}
+summary {
+    cursor: pointer;
+}
+.time {
+    color: #808080;
+}
+.nick {
+    font-weight: bold;
+}
+.content {
+    white-space: pre-wrap;
+}
+.placeholder {
+    color: #808080;
+    font-style: italic;
+}
+a {
+    color: #5f87ff;
+}
+";
+
+pub async fn export<W: Write>(
+    vault: &EuphRoomVault,
+    out: &mut W,
+    selection: &Selection,
+    timestamps: &Timestamps,
+) -> anyhow::Result<()> {
+    let room = vault.room();
+    let title = escape(&format!("&{} ({})", room.name, room.domain));
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html>")?;
+    writeln!(out, "<head>")?;
+    writeln!(out, "<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>{title}</title>")?;
+    writeln!(out, "<style>{STYLE}</style>")?;
+    writeln!(out, "</head>")?;
+    writeln!(out, "<body>")?;
+    writeln!(out, "<h1>{title}</h1>")?;
+
+    let mut exported_trees = 0;
+    let mut exported_msgs = 0;
+    let mut root_id = selection.first_root_id(vault).await?;
+    while let Some(some_root_id) = root_id {
+        let tree = vault.tree(some_root_id).await?;
+        write_tree(out, &room.domain, timestamps, &tree, some_root_id)?;
+        root_id = selection.next_root_id(vault, some_root_id).await?;
+
+        exported_trees += 1;
+        exported_msgs += tree.len();
+
+        if exported_trees % 10000 == 0 {
+            eprintln!("  {exported_trees} trees, {exported_msgs} messages")
+        }
+    }
+
+    writeln!(out, "</body>")?;
+    writeln!(out, "</html>")?;
+
+    eprintln!("  {exported_trees} trees, {exported_msgs} messages in total");
+    Ok(())
+}
+
+/// Write a message and its replies.
+///
+/// Messages with replies become a `<details>` element so their subtree can be
+/// collapsed.
+fn write_tree<W: Write>(
+    out: &mut W,
+    domain: &str,
+    timestamps: &Timestamps,
+    tree: &Tree<SmallMessage>,
+    id: MessageId,
+) -> anyhow::Result<()> {
+    let msg = match tree.msg(&id) {
+        Some(msg) => format_msg(domain, timestamps, msg.time, &msg.nick, &msg.content),
+        None => "<span class=\"placeholder\">[...]</span>".to_string(),
+    };
+
+    let Some(children) = tree.children(&id) else {
+        writeln!(out, "<div class=\"msg\">{msg}</div>")?;
+        return Ok(());
+    };
+
+    writeln!(out, "<details open>")?;
+    writeln!(out, "<summary>{msg}</summary>")?;
+    for child in children {
+        write_tree(out, domain, timestamps, tree, *child)?;
+    }
+    writeln!(out, "</details>")?;
+
+    Ok(())
+}
+
+fn format_msg(
+    domain: &str,
+    timestamps: &Timestamps,
+    time: Time,
+    nick: &str,
+    content: &str,
+) -> String {
+    format!(
+        "<span class=\"time\">{}</span> {} <span class=\"content\">{}</span>",
+        escape(&timestamps.format(time)),
+        format_nick(nick),
+        format_content(domain, content),
+    )
+}
+
+fn nick_color(nick: &str) -> String {
+    let (r, g, b) = euph::nick_color(nick);
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+fn format_nick(nick: &str) -> String {
+    format!(
+        "<span class=\"nick\" style=\"color: {}\">[{}]</span>",
+        nick_color(nick),
+        escape(nick),
+    )
+}
+
+enum Highlight {
+    Url,
+    Room,
+    Mention,
+}
+
+/// Escape the content and turn urls and room names into links.
+fn format_content(domain: &str, content: &str) -> String {
+    let mut highlights = LinkFinder::new()
+        .url_must_have_scheme(false)
+        .kinds(&[LinkKind::Url])
+        .links(content)
+        .map(|link| (link.start()..link.end(), Highlight::Url))
+        .collect::<Vec<_>>();
+
+    // Rooms and mentions inside of urls are part of the url
+    for (span, range) in euph::find_spans(content) {
+        let highlight = match span {
+            SpanType::Room => Highlight::Room,
+            SpanType::Mention => Highlight::Mention,
+            SpanType::Emoji => continue,
+        };
+        if highlights
+            .iter()
+            .all(|(r, _)| range.end <= r.start || r.end <= range.start)
+        {
+            highlights.push((range, highlight));
+        }
+    }
+
+    highlights.sort_unstable_by_key(|(range, _)| range.start);
+
+    let mut result = String::new();
+    let mut last_end = 0;
+    for (range, highlight) in highlights {
+        result.push_str(&escape(&content[last_end..range.start]));
+        let text = &content[range.clone()];
+        match highlight {
+            Highlight::Url => {
+                let href = if text.contains("://") {
+                    text.to_string()
+                } else {
+                    format!("https://{text}")
+                };
+                result.push_str(&format!(
+                    "<a href=\"{}\">{}</a>",
+                    escape(&href),
+                    escape(text)
+                ));
+            }
+            Highlight::Room => {
+                let href = format!("https://{domain}/room/{}/", &text[1..]);
+                result.push_str(&format!(
+                    "<a href=\"{}\">{}</a>",
+                    escape(&href),
+                    escape(text)
+                ));
+            }
+            Highlight::Mention => {
+                result.push_str(&format!(
+                    "<span style=\"color: {}\">{}</span>",
+                    nick_color(&text[1..]),
+                    escape(text)
+                ));
+            }
+        }
+        last_end = range.end;
+    }
+    result.push_str(&escape(&content[last_end..]));
+
+    result
+}
+
+fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for char in text.chars() {
+        match char {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            _ => result.push(char),
+        }
+    }
+    result
+}
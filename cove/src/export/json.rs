@@ -1,21 +1,21 @@
 use std::io::Write;
 
+use euphoxide::api::{Message, MessageId};
+
 use crate::vault::EuphRoomVault;
 
-const CHUNK_SIZE: usize = 10000;
+use super::Selection;
 
-pub async fn export<W: Write>(vault: &EuphRoomVault, file: &mut W) -> anyhow::Result<()> {
+pub async fn export<W: Write>(
+    vault: &EuphRoomVault,
+    file: &mut W,
+    selection: &Selection,
+) -> anyhow::Result<()> {
     write!(file, "[")?;
 
     let mut total = 0;
-    let mut last_msg_id = None;
-    loop {
-        let messages = vault.chunk_after(last_msg_id, CHUNK_SIZE).await?;
-        last_msg_id = Some(match messages.last() {
-            Some(last_msg) => last_msg.id,
-            None => break, // No more messages, export finished
-        });
-
+    let mut chunks = selection.chunks(vault);
+    while let Some(messages) = chunks.next().await? {
         for message in messages {
             if total == 0 {
                 writeln!(file)?;
@@ -37,19 +37,21 @@ pub async fn export<W: Write>(vault: &EuphRoomVault, file: &mut W) -> anyhow::Re
     Ok(())
 }
 
-pub async fn export_lines<W: Write>(vault: &EuphRoomVault, file: &mut W) -> anyhow::Result<()> {
-    let mut total = 0;
+/// Export the selected messages of a room as JSON Lines.
+///
+/// Returns the id of the last exported message.
+pub async fn export_lines<W: Write>(
+    vault: &EuphRoomVault,
+    file: &mut W,
+    selection: &Selection,
+) -> anyhow::Result<Option<MessageId>> {
     let mut last_msg_id = None;
-    loop {
-        let messages = vault.chunk_after(last_msg_id, CHUNK_SIZE).await?;
-        last_msg_id = Some(match messages.last() {
-            Some(last_msg) => last_msg.id,
-            None => break, // No more messages, export finished
-        });
-
+    let mut total = 0;
+    let mut chunks = selection.chunks(vault);
+    while let Some(messages) = chunks.next().await? {
         for message in messages {
-            serde_json::to_writer(&mut *file, &message)?; // Fancy reborrow! :D
-            writeln!(file)?;
+            write_line(file, &message)?;
+            last_msg_id = Some(message.id);
             total += 1;
         }
 
@@ -59,5 +61,12 @@ pub async fn export_lines<W: Write>(vault: &EuphRoomVault, file: &mut W) -> anyh
     }
 
     eprintln!("  {total} messages in total");
+    Ok(last_msg_id)
+}
+
+/// Write a single message as a line in the JSON Lines format.
+pub fn write_line<W: Write>(file: &mut W, message: &Message) -> anyhow::Result<()> {
+    serde_json::to_writer(&mut *file, message)?; // Fancy reborrow! :D
+    writeln!(file)?;
     Ok(())
 }
@@ -1,21 +1,88 @@
 use std::io::Write;
 
-use euphoxide::api::MessageId;
+use euphoxide::api::{MessageId, Time};
+use jiff::{civil, fmt::strtime, tz::TimeZone};
 use unicode_width::UnicodeWidthStr;
 
 use crate::{euph::SmallMessage, store::Tree, vault::EuphRoomVault};
 
-const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
-const TIME_EMPTY: &str = "                   ";
+use super::Selection;
 
-pub async fn export<W: Write>(vault: &EuphRoomVault, out: &mut W) -> anyhow::Result<()> {
+pub const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Age in seconds the newest message of a tree must have before incremental
+/// exports consider the tree closed.
+///
+/// Replies can't be appended to trees that were already exported, so younger
+/// trees are held back until a later export.
+const CLOSED_TREE_AGE: i64 = 24 * 60 * 60;
+
+/// How timestamps are displayed in text based exports.
+#[derive(Debug, Clone)]
+pub struct Timestamps {
+    format: String,
+    tz: TimeZone,
+    /// Whitespace as wide as a formatted timestamp.
+    empty: String,
+}
+
+impl Timestamps {
+    /// Fails if `format` is not a valid `strftime` format string.
+    pub fn new(format: String, tz: TimeZone) -> anyhow::Result<Self> {
+        // The width of a formatted timestamp may depend on the time, e. g. with
+        // month names or unpadded days, so the widest of a few times is used.
+        let mut width = 0;
+        for month in 1..=12 {
+            for (day, hour) in [(1, 0), (12, 12), (28, 23)] {
+                let example = civil::date(2000, month, day).at(hour, 0, 0, 0);
+                let example = strtime::format(&format, &example.to_zoned(tz.clone())?)?;
+                width = width.max(example.width());
+            }
+        }
+        let empty = " ".repeat(width);
+        Ok(Self { format, tz, empty })
+    }
+
+    pub fn format(&self, time: Time) -> String {
+        let time = time.as_timestamp().to_zoned(self.tz.clone());
+        time.strftime(&self.format).to_string()
+    }
+}
+
+/// Export the selected trees of a room.
+///
+/// If `only_closed` is set, the export stops at the first tree that might
+/// still receive new messages, either because its newest message is younger
+/// than [`CLOSED_TREE_AGE`] or because it comes after a gap in the room's
+/// history.
+///
+/// Returns the id of the last exported tree's root.
+pub async fn export<W: Write>(
+    vault: &EuphRoomVault,
+    out: &mut W,
+    selection: &Selection,
+    timestamps: &Timestamps,
+    only_closed: bool,
+) -> anyhow::Result<Option<MessageId>> {
+    let closed_before = Time(Time::now().0 - CLOSED_TREE_AGE);
+    let first_gap = if only_closed {
+        first_gap_after(vault, selection.after).await?
+    } else {
+        None
+    };
+
+    let mut last_root_id = None;
     let mut exported_trees = 0;
     let mut exported_msgs = 0;
-    let mut root_id = vault.first_root_id().await?;
+    let mut root_id = selection.first_root_id(vault).await?;
     while let Some(some_root_id) = root_id {
         let tree = vault.tree(some_root_id).await?;
-        write_tree(out, &tree, some_root_id, 0)?;
-        root_id = vault.next_root_id(some_root_id).await?;
+        if only_closed && !is_closed(&tree, closed_before, first_gap) {
+            break;
+        }
+        write_tree(out, timestamps, &tree, some_root_id, 0)?;
+        last_root_id = Some(some_root_id);
+        root_id = selection.next_root_id(vault, some_root_id).await?;
 
         exported_trees += 1;
         exported_msgs += tree.len();
@@ -26,11 +93,36 @@ pub async fn export<W: Write>(vault: &EuphRoomVault, out: &mut W) -> anyhow::Res
     }
     eprintln!("  {exported_trees} trees, {exported_msgs} messages in total");
 
-    Ok(())
+    Ok(last_root_id)
+}
+
+/// Start of the first gap in the history of a room that is not entirely before
+/// `after`.
+///
+/// Backfilling the gap might add messages to any tree after its start. Gaps at
+/// the beginning of the room's history are ignored since they only contain
+/// messages older than any exported tree.
+async fn first_gap_after(
+    vault: &EuphRoomVault,
+    after: Option<MessageId>,
+) -> anyhow::Result<Option<MessageId>> {
+    let gaps = vault.gaps().await?;
+    Ok(gaps
+        .into_iter()
+        .filter(|(_, before)| after.is_none_or(|after| *before > after))
+        .find_map(|(start, _)| start))
+}
+
+fn is_closed(tree: &Tree<SmallMessage>, closed_before: Time, first_gap: Option<MessageId>) -> bool {
+    if first_gap.is_some_and(|gap| *tree.root() > gap) {
+        return false;
+    }
+    tree.msgs().all(|msg| msg.time.0 < closed_before.0)
 }
 
 fn write_tree<W: Write>(
     out: &mut W,
+    timestamps: &Timestamps,
     tree: &Tree<SmallMessage>,
     id: MessageId,
     indent: usize,
@@ -38,41 +130,56 @@ fn write_tree<W: Write>(
     let indent_string = "| ".repeat(indent);
 
     if let Some(msg) = tree.msg(&id) {
-        write_msg(out, &indent_string, msg)?;
+        write_msg(
+            out,
+            timestamps,
+            &indent_string,
+            msg.time,
+            &msg.nick,
+            &msg.content,
+        )?;
     } else {
-        write_placeholder(out, &indent_string)?;
+        write_placeholder(out, timestamps, &indent_string)?;
     }
 
     if let Some(children) = tree.children(&id) {
         for child in children {
-            write_tree(out, tree, *child, indent + 1)?;
+            write_tree(out, timestamps, tree, *child, indent + 1)?;
         }
     }
 
     Ok(())
 }
 
-fn write_msg<W: Write>(
+pub fn write_msg<W: Write>(
     file: &mut W,
+    timestamps: &Timestamps,
     indent_string: &str,
-    msg: &SmallMessage,
+    time: Time,
+    nick: &str,
+    content: &str,
 ) -> anyhow::Result<()> {
-    let nick = &msg.nick;
+    let time = timestamps.format(time);
+    let time_empty = " ".repeat(time.width());
     let nick_empty = " ".repeat(nick.width());
 
-    for (i, line) in msg.content.lines().enumerate() {
+    for (i, line) in content.lines().enumerate() {
         if i == 0 {
-            let time = msg.time.as_timestamp().strftime(TIME_FORMAT);
             writeln!(file, "{time} {indent_string}[{nick}] {line}")?;
         } else {
-            writeln!(file, "{TIME_EMPTY} {indent_string}| {nick_empty} {line}")?;
+            writeln!(file, "{time_empty} {indent_string}| {nick_empty} {line}")?;
         }
     }
 
     Ok(())
 }
 
-fn write_placeholder<W: Write>(file: &mut W, indent_string: &str) -> anyhow::Result<()> {
-    writeln!(file, "{TIME_EMPTY} {indent_string}[...]")?;
+fn write_placeholder<W: Write>(
+    file: &mut W,
+    timestamps: &Timestamps,
+    indent_string: &str,
+) -> anyhow::Result<()> {
+    let time_empty = &timestamps.empty;
+    writeln!(file, "{time_empty} {indent_string}[...]")?;
     Ok(())
 }
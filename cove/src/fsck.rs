@@ -0,0 +1,86 @@
+//! Check the vault for inconsistencies and optionally repair them.
+
+use crate::vault::{EuphVault, FsckReport};
+
+#[derive(Debug, clap::Parser)]
+pub struct Args {
+    /// Repair problems where possible.
+    ///
+    /// Malformed spans are removed, overlapping spans are merged, and missing
+    /// room entries are recreated.
+    #[arg(long, short)]
+    fix: bool,
+}
+
+/// Print the problems found in each room.
+///
+/// Returns the amount of rooms with problems.
+fn print_reports(reports: &[FsckReport]) -> usize {
+    let mut problems = 0;
+    for report in reports {
+        if report.is_ok() && report.orphaned_parents == 0 && report.msgs_outside_spans == 0 {
+            continue;
+        }
+
+        println!("&{} on {}:", report.room.name, report.room.domain);
+        if report.missing_room {
+            println!("  room entry is missing");
+        }
+        if report.malformed_spans > 0 {
+            println!("  {} malformed spans", report.malformed_spans);
+        }
+        if report.overlapping_spans > 0 {
+            println!("  {} overlapping spans", report.overlapping_spans);
+        }
+        if report.orphaned_parents > 0 {
+            // Usually messages deleted by moderators, which the server no
+            // longer returns
+            println!(
+                "  info: {} missing parents ({} inside spans), probably deleted messages",
+                report.orphaned_parents, report.missing_parents_in_spans
+            );
+        }
+        if report.msgs_outside_spans > 0 {
+            // They lie in gaps that are filled by downloading the room history
+            println!(
+                "  info: {} messages outside of spans",
+                report.msgs_outside_spans
+            );
+        }
+
+        if !report.is_ok() {
+            problems += 1;
+        }
+    }
+    problems
+}
+
+pub async fn fsck(vault: &EuphVault, args: Args) -> anyhow::Result<()> {
+    eprintln!("Checking vault");
+    let reports = vault.fsck(args.fix).await?;
+    let problems = print_reports(&reports);
+
+    eprintln!("  {} rooms checked", reports.len());
+    if problems == 0 {
+        eprintln!("  No problems found");
+        return Ok(());
+    }
+    if !args.fix {
+        eprintln!("  {problems} rooms with problems, run with --fix to repair");
+        return Ok(());
+    }
+    eprintln!("  {problems} rooms with problems, repairing");
+
+    eprintln!("Checking vault again");
+    let reports = vault.fsck(false).await?;
+    let reports = reports
+        .into_iter()
+        .filter(|report| !report.is_ok())
+        .collect::<Vec<_>>();
+    match print_reports(&reports) {
+        0 => eprintln!("  All problems repaired"),
+        n => eprintln!("  {n} rooms with problems that could not be repaired"),
+    }
+
+    Ok(())
+}
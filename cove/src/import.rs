@@ -0,0 +1,126 @@
+//! Import room logs exported via `cove export` into the vault.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use euphoxide::api::{Message, Time};
+
+use crate::vault::{EuphVault, RoomIdentifier};
+
+const CHUNK_SIZE: usize = 10000;
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Format {
+    /// Array of message objects in the same format as the euphoria API uses.
+    Json,
+    /// Message objects in the same format as the euphoria API uses, one per
+    /// line (https://jsonlines.org/).
+    JsonLines,
+}
+
+impl Format {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::JsonLines => "json lines",
+        }
+    }
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct Args {
+    /// Room to import the messages into.
+    room: String,
+
+    /// File to import the messages from.
+    file: PathBuf,
+
+    /// Domain to resolve the room name with.
+    #[arg(long, short, default_value = "euphoria.leet.nu")]
+    domain: String,
+
+    /// Format of the input file.
+    ///
+    /// If not specified, files ending in `.jsonl` are assumed to be in the
+    /// json lines format and all other files in the json format.
+    #[arg(long, short, value_enum)]
+    format: Option<Format>,
+
+    /// Treat the file as containing the room's complete history up to its
+    /// newest message.
+    ///
+    /// Otherwise, cove doesn't know whether messages are missing between the
+    /// imported messages and will later download the surrounding history
+    /// again.
+    #[arg(long)]
+    complete_history: bool,
+}
+
+fn read_json(file: &Path) -> anyhow::Result<Vec<Message>> {
+    let reader = BufReader::new(File::open(file)?);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+fn read_json_lines(file: &Path) -> anyhow::Result<Vec<Message>> {
+    let reader = BufReader::new(File::open(file)?);
+    let mut msgs = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let msg = serde_json::from_str(&line).with_context(|| format!("invalid line {}", i + 1))?;
+        msgs.push(msg);
+    }
+    Ok(msgs)
+}
+
+pub async fn import(vault: &EuphVault, args: Args) -> anyhow::Result<()> {
+    let format = args.format.unwrap_or_else(|| {
+        if args.file.extension().is_some_and(|e| e == "jsonl") {
+            Format::JsonLines
+        } else {
+            Format::Json
+        }
+    });
+
+    eprintln!(
+        "Importing &{} from {} as {}",
+        args.room,
+        args.file.to_string_lossy(),
+        format.name()
+    );
+
+    let mut msgs = match format {
+        Format::Json => read_json(&args.file)?,
+        Format::JsonLines => read_json_lines(&args.file)?,
+    };
+    msgs.sort_unstable_by_key(|msg| msg.id);
+    msgs.dedup_by_key(|msg| msg.id);
+
+    let vault = vault.room(RoomIdentifier::new(args.domain, args.room));
+    let time = Time::now();
+
+    let total = msgs.len();
+    let mut imported = 0;
+    let mut processed = 0;
+    let mut msgs = msgs.into_iter().peekable();
+    while msgs.peek().is_some() {
+        let chunk = msgs.by_ref().take(CHUNK_SIZE).collect::<Vec<_>>();
+        processed += chunk.len();
+        imported += vault
+            .import_msgs(chunk, time, args.complete_history)
+            .await?;
+
+        if processed % 100000 == 0 {
+            eprintln!("  {processed} messages");
+        }
+    }
+
+    eprintln!("  {total} messages in total, {imported} of which were new");
+    Ok(())
+}
@@ -1,7 +1,7 @@
 // TODO Remove unnecessary Debug impls and compare compile times
 // TODO Invoke external notification command?
 
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use anyhow::Context;
 use clap::Parser;
@@ -18,10 +18,18 @@ use crate::{
     version::{NAME, VERSION},
 };
 
+mod backup;
 mod euph;
 mod export;
+mod fsck;
+mod import;
 mod logger;
 mod macros;
+mod merge;
+mod move_domain;
+mod resync;
+mod search;
+mod stats;
 mod store;
 mod ui;
 mod util;
@@ -35,8 +43,28 @@ enum Command {
     Run,
     /// Export room logs as plain text files.
     Export(export::Args),
+    /// Import room logs exported as json or json lines.
+    Import(import::Args),
+    /// Merge the rooms and messages of another vault into this vault.
+    Merge(merge::Args),
+    /// Move rooms from one domain to another, merging them with existing rooms.
+    MoveDomain(move_domain::Args),
+    /// Search the vault for messages.
+    Search(search::Args),
+    /// Redownload recent messages of a room to find edits and deletions.
+    Resync(resync::Args),
+    /// Write a consistent snapshot of the vault to a file.
+    Backup(backup::Args),
+    /// Check the vault for inconsistencies.
+    Fsck(fsck::Args),
+    /// Show an overview of the rooms stored in the vault.
+    Stats(stats::Args),
     /// Compact and clean up vault.
-    Gc,
+    Gc {
+        /// Delete messages according to the `retention` config options first.
+        #[arg(long, short)]
+        prune: bool,
+    },
     /// Clear euphoria session cookies.
     ClearCookies {
         /// Clear cookies for a specific domain only.
@@ -119,6 +147,29 @@ fn update_config_with_args(config: &mut Config, args: &Args) {
         config.data_dir = Some(base_dirs.home_dir().join(data_dir));
     }
 
+    // Resolve the backup dir relative to the user's home directory, if
+    // possible.
+    if let Some(dir) = &config.backup.dir {
+        let base_dirs = BaseDirs::new().expect("failed to find home directory");
+        config.backup.dir = Some(base_dirs.home_dir().join(dir));
+    }
+
+    // Resolve certificate paths relative to the user's home directory, if
+    // possible.
+    if config
+        .euph
+        .servers
+        .values()
+        .any(|s| !s.trusted_root_certs.is_empty())
+    {
+        let base_dirs = BaseDirs::new().expect("failed to find home directory");
+        for server in config.euph.servers.values_mut() {
+            for path in &mut server.trusted_root_certs {
+                *path = base_dirs.home_dir().join(&*path);
+            }
+        }
+    }
+
     config.ephemeral |= args.ephemeral;
     if let Some(method) = args.width_estimation_method {
         config.width_estimation_method = match method {
@@ -142,6 +193,20 @@ fn open_vault(config: &Config, dirs: &ProjectDirs) -> anyhow::Result<Vault> {
     Ok(vault)
 }
 
+/// Open the vault without writing to it, so that it can be used while another
+/// cove instance is running.
+fn open_vault_read_only(config: &Config, dirs: &ProjectDirs) -> anyhow::Result<Vault> {
+    let vault = if config.ephemeral {
+        vault::launch_in_memory()?
+    } else {
+        let data_dir = data_dir(config, dirs);
+        eprintln!("Data dir:    {}", data_dir.to_string_lossy());
+        vault::launch_read_only(&data_dir.join("vault.db"))?
+    };
+
+    Ok(vault)
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
@@ -166,7 +231,15 @@ async fn main() -> anyhow::Result<()> {
     match args.command.unwrap_or_default() {
         Command::Run => run(logger, logger_rx, config, &dirs).await?,
         Command::Export(args) => export(config, &dirs, args).await?,
-        Command::Gc => gc(config, &dirs).await?,
+        Command::Import(args) => import(config, &dirs, args).await?,
+        Command::Merge(args) => merge(config, &dirs, args).await?,
+        Command::MoveDomain(args) => move_domain(config, &dirs, args).await?,
+        Command::Search(args) => search(config, &dirs, args).await?,
+        Command::Resync(args) => resync(config, &dirs, args).await?,
+        Command::Backup(args) => backup(config, &dirs, args).await?,
+        Command::Fsck(args) => fsck(config, &dirs, args).await?,
+        Command::Stats(args) => stats(config, &dirs, args).await?,
+        Command::Gc { prune } => gc(config, &dirs, prune).await?,
         Command::ClearCookies { domain } => clear_cookies(config, &dirs, domain).await?,
         Command::HelpConfig => help_config(),
     }
@@ -192,6 +265,30 @@ async fn run(
 
     let vault = open_vault(config, dirs)?;
 
+    let pruned = euph::prune_rooms(config, &vault.euph()).await?;
+    if pruned > 0 {
+        eprintln!("Pruned {pruned} messages");
+    }
+
+    let backup_task = match config.backup.interval_minutes {
+        Some(minutes) if !config.ephemeral => {
+            let dir = config
+                .backup
+                .dir
+                .clone()
+                .unwrap_or_else(|| data_dir(config, dirs).join("backups"));
+            let interval = Duration::from_secs(minutes.max(1) * 60);
+            let keep = usize::try_from(config.backup.keep).unwrap_or(usize::MAX);
+            Some(tokio::task::spawn(backup::regularly_backup(
+                vault.clone(),
+                dir,
+                interval,
+                keep,
+            )))
+        }
+        _ => None,
+    };
+
     let mut terminal = Terminal::new()?;
     terminal.set_measuring(config.measure_widths);
     terminal.set_width_estimation_method(match config.width_estimation_method {
@@ -201,6 +298,10 @@ async fn run(
     Ui::run(config, tz, &mut terminal, vault.clone(), logger, logger_rx).await?;
     drop(terminal);
 
+    if let Some(backup_task) = backup_task {
+        backup_task.abort();
+    }
+
     vault.close().await;
     Ok(())
 }
@@ -209,18 +310,130 @@ async fn export(
     config: &'static Config,
     dirs: &ProjectDirs,
     args: export::Args,
+) -> anyhow::Result<()> {
+    let tz = util::load_time_zone(config.time_zone_ref()).context("failed to load time zone")?;
+
+    let vault = open_vault_read_only(config, dirs)?;
+
+    export::export(&vault.euph(), args, tz).await?;
+
+    vault.close().await;
+    Ok(())
+}
+
+async fn import(
+    config: &'static Config,
+    dirs: &ProjectDirs,
+    args: import::Args,
 ) -> anyhow::Result<()> {
     let vault = open_vault(config, dirs)?;
 
-    export::export(&vault.euph(), args).await?;
+    import::import(&vault.euph(), args).await?;
 
     vault.close().await;
     Ok(())
 }
 
-async fn gc(config: &'static Config, dirs: &ProjectDirs) -> anyhow::Result<()> {
+async fn merge(
+    config: &'static Config,
+    dirs: &ProjectDirs,
+    args: merge::Args,
+) -> anyhow::Result<()> {
     let vault = open_vault(config, dirs)?;
 
+    merge::merge(&vault.euph(), args).await?;
+
+    vault.close().await;
+    Ok(())
+}
+
+async fn move_domain(
+    config: &'static Config,
+    dirs: &ProjectDirs,
+    args: move_domain::Args,
+) -> anyhow::Result<()> {
+    let vault = open_vault(config, dirs)?;
+
+    move_domain::move_domain(&vault.euph(), args).await?;
+
+    vault.close().await;
+    Ok(())
+}
+
+async fn search(
+    config: &'static Config,
+    dirs: &ProjectDirs,
+    args: search::Args,
+) -> anyhow::Result<()> {
+    let tz = util::load_time_zone(config.time_zone_ref()).context("failed to load time zone")?;
+
+    let vault = open_vault_read_only(config, dirs)?;
+
+    search::search(&vault.euph(), args, tz).await?;
+
+    vault.close().await;
+    Ok(())
+}
+
+async fn resync(
+    config: &'static Config,
+    dirs: &ProjectDirs,
+    args: resync::Args,
+) -> anyhow::Result<()> {
+    let vault = open_vault(config, dirs)?;
+
+    resync::resync(config, &vault.euph(), args).await?;
+
+    vault.close().await;
+    Ok(())
+}
+
+async fn backup(
+    config: &'static Config,
+    dirs: &ProjectDirs,
+    args: backup::Args,
+) -> anyhow::Result<()> {
+    let vault = open_vault_read_only(config, dirs)?;
+
+    backup::backup(&vault, args).await?;
+
+    vault.close().await;
+    Ok(())
+}
+
+async fn fsck(config: &'static Config, dirs: &ProjectDirs, args: fsck::Args) -> anyhow::Result<()> {
+    let vault = open_vault(config, dirs)?;
+
+    fsck::fsck(&vault.euph(), args).await?;
+
+    vault.close().await;
+    Ok(())
+}
+
+async fn stats(
+    config: &'static Config,
+    dirs: &ProjectDirs,
+    args: stats::Args,
+) -> anyhow::Result<()> {
+    let tz = util::load_time_zone(config.time_zone_ref()).context("failed to load time zone")?;
+
+    let vault = open_vault_read_only(config, dirs)?;
+
+    stats::stats(&vault.euph(), args, tz).await?;
+
+    vault.close().await;
+    Ok(())
+}
+
+async fn gc(config: &'static Config, dirs: &ProjectDirs, prune: bool) -> anyhow::Result<()> {
+    let vault = open_vault(config, dirs)?;
+
+    if prune {
+        eprintln!("Pruning messages");
+        let pruned = euph::prune_rooms(config, &vault.euph()).await?;
+        eprintln!("  {pruned} messages deleted");
+    }
+
     eprintln!("Cleaning up and compacting vault");
     eprintln!("This may take a while...");
     vault.gc().await?;
@@ -0,0 +1,51 @@
+//! Merge the contents of another vault into the current vault.
+
+use std::path::PathBuf;
+
+use crate::vault::{EuphVault, MergeSeen};
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Seen {
+    /// Keep the seen status of messages already in this vault.
+    Ours,
+    /// Use the seen status from the other vault.
+    Theirs,
+    /// Messages are seen if they are seen in either vault.
+    Any,
+    /// Messages are seen only if they are seen in both vaults.
+    All,
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct Args {
+    /// Path to the other vault.
+    ///
+    /// The other vault must not be in use by a running cove instance.
+    other: PathBuf,
+
+    /// How to combine the seen status of messages present in both vaults.
+    ///
+    /// Messages only present in the other vault keep their seen status.
+    #[arg(long, short, value_enum, default_value_t = Seen::Any)]
+    seen: Seen,
+}
+
+pub async fn merge(vault: &EuphVault, args: Args) -> anyhow::Result<()> {
+    if !args.other.is_file() {
+        anyhow::bail!("{} is not a file", args.other.to_string_lossy());
+    }
+
+    let seen = match args.seen {
+        Seen::Ours => MergeSeen::Ours,
+        Seen::Theirs => MergeSeen::Theirs,
+        Seen::Any => MergeSeen::Any,
+        Seen::All => MergeSeen::All,
+    };
+
+    eprintln!("Merging {} into vault", args.other.to_string_lossy());
+    eprintln!("This may take a while...");
+    let new_msgs = vault.merge(args.other, seen).await?;
+    eprintln!("  {new_msgs} new messages");
+
+    Ok(())
+}
@@ -0,0 +1,35 @@
+//! Move rooms from one domain to another inside the vault.
+
+use crate::vault::EuphVault;
+
+#[derive(Debug, clap::Parser)]
+pub struct Args {
+    /// Domain to move the rooms from.
+    old: String,
+
+    /// Domain to move the rooms to.
+    new: String,
+
+    /// Only move this room.
+    ///
+    /// Cookies are only moved if the whole domain is moved.
+    #[arg(long, short)]
+    room: Option<String>,
+}
+
+pub async fn move_domain(vault: &EuphVault, args: Args) -> anyhow::Result<()> {
+    if args.old == args.new {
+        anyhow::bail!("old and new domain must be different");
+    }
+
+    match &args.room {
+        Some(room) => eprintln!("Moving &{room} from {} to {}", args.old, args.new),
+        None => eprintln!("Moving rooms from {} to {}", args.old, args.new),
+    }
+    eprintln!("Rooms already present on {} are merged", args.new);
+
+    let moved = vault.move_rooms(args.old, args.new, args.room).await?;
+    eprintln!("  {moved} rooms moved");
+
+    Ok(())
+}
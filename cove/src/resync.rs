@@ -0,0 +1,75 @@
+//! Redownload recent messages of a room to find edits and deletions.
+
+use anyhow::bail;
+use cove_config::Config;
+use euphoxide::api::{Data, ParsedPacket};
+use euphoxide_client::{Client, ClientConfig, ClientEvent};
+use tokio::sync::mpsc;
+
+use crate::{
+    euph,
+    vault::{EuphVault, RoomIdentifier},
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct Args {
+    room: String,
+
+    /// Domain to resolve the room name with.
+    #[arg(long, short, default_value = "euphoria.leet.nu")]
+    domain: String,
+
+    /// Amount of windows of 1000 messages to redownload, starting with the
+    /// newest messages.
+    #[arg(long, short, default_value_t = 5)]
+    windows: usize,
+}
+
+pub async fn resync(config: &Config, vault: &EuphVault, args: Args) -> anyhow::Result<()> {
+    let room = RoomIdentifier::new(args.domain, args.room);
+    if !vault.rooms().await?.contains(&room) {
+        bail!("&{} on {} is not in the vault", room.name, room.domain);
+    }
+    let room_config = config.euph_room(&room.domain, &room.name);
+
+    let cookies = vault.cookies(room.domain.clone()).await?;
+    let server_config = euph::server_config(config, &room.domain, cookies);
+
+    let mut client_config = ClientConfig::new(server_config, room.name.clone());
+    client_config.password = room_config.password.clone();
+
+    eprintln!("Connecting to &{} on {}", room.name, room.domain);
+    let (tx, mut rx) = mpsc::channel(10);
+    let client = Client::new(0, client_config, tx);
+
+    let (conn, own_user_id) = loop {
+        match rx.recv().await.map(|(_, event)| event) {
+            Some(ClientEvent::Packet {
+                conn,
+                packet:
+                    ParsedPacket {
+                        content: Ok(Data::SnapshotEvent(snapshot)),
+                        ..
+                    },
+                ..
+            }) => break (conn, snapshot.identity),
+            Some(ClientEvent::Disconnected | ClientEvent::Stopped) | None => {
+                bail!("failed to join &{}", room.name)
+            }
+            Some(_) => {}
+        }
+    };
+
+    // The client stops making progress if its events aren't consumed
+    tokio::task::spawn(async move { while rx.recv().await.is_some() {} });
+
+    eprintln!("Redownloading up to {} windows", args.windows);
+    let room_vault = vault.room(room.clone());
+    let changes = euph::resync_logs(&room_vault, &conn, Some(own_user_id), args.windows).await?;
+    eprintln!("Found {changes} changed messages");
+
+    let cookies = client.config().server.cookies.lock().unwrap().clone();
+    vault.set_cookies(room.domain, cookies).await?;
+
+    Ok(())
+}
@@ -0,0 +1,194 @@
+//! Search the vault for messages from the command line.
+
+use std::io::{self, BufWriter, Write};
+
+use anyhow::Context;
+use euphoxide::api::{Message, Time, UserId};
+use jiff::{Timestamp, tz::TimeZone};
+use regex::Regex;
+
+use crate::{
+    export::{
+        json,
+        text::{self, Timestamps},
+    },
+    util,
+    vault::{EuphVault, MsgFilter, RoomIdentifier},
+};
+
+const CHUNK_SIZE: usize = 10000;
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Format {
+    /// Human-readable messages, one after another, below the room they were
+    /// found in.
+    Text,
+    /// Message objects in the same format as the euphoria API uses, one per
+    /// line (https://jsonlines.org/), like `cove export -f json-lines`.
+    ///
+    /// Results are grouped by room, and the room is printed to stderr before
+    /// its results.
+    JsonLines,
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct Args {
+    /// Rooms to search in. If no rooms are specified, all rooms are searched.
+    rooms: Vec<String>,
+
+    /// Only search rooms on this domain.
+    ///
+    /// Room names are resolved with `euphoria.leet.nu` if this is not
+    /// specified.
+    #[arg(long, short)]
+    domain: Option<String>,
+
+    /// Only find messages sent with this exact nick.
+    #[arg(long, short)]
+    nick: Option<String>,
+
+    /// Only find messages sent by this user id (e. g. `agent:...`).
+    #[arg(long, short)]
+    user_id: Option<String>,
+
+    /// Only find messages sent at or after this time (e. g.
+    /// `2024-01-01T00:00:00Z`).
+    #[arg(long, short)]
+    since: Option<Timestamp>,
+
+    /// Only find messages sent before this time (e. g.
+    /// `2024-01-01T00:00:00Z`).
+    #[arg(long)]
+    until: Option<Timestamp>,
+
+    /// Only find messages whose content matches this regular expression.
+    #[arg(long, short)]
+    content: Option<Regex>,
+
+    /// Format of the output.
+    #[arg(long, short, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Format of timestamps in the text format.
+    ///
+    /// Uses `strftime` syntax, e. g. `%Y-%m-%d %H:%M:%S`.
+    #[arg(long, default_value_t = Into::into(text::TIME_FORMAT))]
+    time_format: String,
+
+    /// Time zone to display timestamps in (e. g. `Europe/Berlin` or `UTC`).
+    ///
+    /// Uses the same format as the `time_zone` config option, which is used by
+    /// default.
+    #[arg(long)]
+    time_zone: Option<String>,
+}
+
+async fn rooms(vault: &EuphVault, args: &Args) -> anyhow::Result<Vec<RoomIdentifier>> {
+    if !args.rooms.is_empty() {
+        let domain = args.domain.as_deref().unwrap_or("euphoria.leet.nu");
+        let mut rooms = args
+            .rooms
+            .iter()
+            .map(|name| RoomIdentifier::new(domain.to_string(), name.clone()))
+            .collect::<Vec<_>>();
+        rooms.dedup();
+        return Ok(rooms);
+    }
+
+    let mut rooms = vault
+        .rooms()
+        .await?
+        .into_iter()
+        .filter(|room| args.domain.as_ref().is_none_or(|d| *d == room.domain))
+        .collect::<Vec<_>>();
+    rooms.sort_unstable();
+    Ok(rooms)
+}
+
+fn write_text<W: Write>(
+    out: &mut W,
+    timestamps: &Timestamps,
+    message: &Message,
+) -> anyhow::Result<()> {
+    text::write_msg(
+        out,
+        timestamps,
+        "",
+        message.time,
+        &message.sender.name,
+        &message.content,
+    )
+}
+
+/// Search the vault for messages.
+///
+/// Times are displayed in the time zone `tz` unless a different time zone is
+/// specified in the args.
+pub async fn search(vault: &EuphVault, args: Args, tz: TimeZone) -> anyhow::Result<()> {
+    let tz = match &args.time_zone {
+        Some(tz) => util::parse_time_zone(tz).context("failed to load time zone")?,
+        None => tz,
+    };
+    let timestamps =
+        Timestamps::new(args.time_format.clone(), tz).context("invalid time format")?;
+
+    let filter = MsgFilter {
+        nick: args.nick.clone(),
+        user_id: args.user_id.clone().map(UserId),
+        since: args.since.map(|t| Time(t.as_second())),
+        until: args.until.map(|t| Time(t.as_second())),
+    };
+
+    let rooms = rooms(vault, &args).await?;
+    if rooms.is_empty() {
+        eprintln!("No rooms to search");
+    }
+
+    let mut out = BufWriter::new(io::stdout());
+    let mut total = 0;
+    for room in rooms {
+        // Results of previous rooms must appear before the room is announced
+        out.flush()?;
+        eprintln!("Searching &{} on {}", room.name, room.domain);
+        let vault = vault.room(room.clone());
+        let mut found_in_room = false;
+
+        let mut last_msg_id = None;
+        loop {
+            let messages = vault
+                .filtered_chunk_after(last_msg_id, CHUNK_SIZE, filter.clone())
+                .await?;
+            last_msg_id = Some(match messages.last() {
+                Some(last_msg) => last_msg.id,
+                None => break, // No more messages, search finished
+            });
+
+            for message in messages {
+                if let Some(regex) = &args.content
+                    && !regex.is_match(&message.content)
+                {
+                    continue;
+                }
+
+                match args.format {
+                    Format::Text => {
+                        if !found_in_room {
+                            if total > 0 {
+                                writeln!(out)?;
+                            }
+                            writeln!(out, "&{} ({})", room.name, room.domain)?;
+                        }
+                        write_text(&mut out, &timestamps, &message)?;
+                    }
+                    Format::JsonLines => json::write_line(&mut out, &message)?,
+                }
+                found_in_room = true;
+                total += 1;
+            }
+        }
+    }
+    out.flush()?;
+
+    eprintln!("  {total} messages found");
+    Ok(())
+}
@@ -0,0 +1,161 @@
+//! Show an overview of the rooms stored in the vault.
+
+use anyhow::Context;
+use jiff::tz::TimeZone;
+use serde_json::json;
+
+use crate::{
+    export::text::{self, Timestamps},
+    util,
+    vault::{EuphVault, RoomStats},
+};
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Format {
+    /// Human-readable table.
+    Table,
+    /// Array of room objects.
+    Json,
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct Args {
+    /// Only show rooms on this domain.
+    #[arg(long, short)]
+    domain: Option<String>,
+
+    /// Format of the output.
+    #[arg(long, short, value_enum, default_value_t = Format::Table)]
+    format: Format,
+
+    /// Time zone to display times in the table in (e. g. `Europe/Berlin` or
+    /// `UTC`).
+    ///
+    /// Uses the same format as the `time_zone` config option, which is used by
+    /// default.
+    #[arg(long)]
+    time_zone: Option<String>,
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next_unit in &UNITS[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next_unit;
+    }
+    format!("{size:.1} {unit}")
+}
+
+fn print_table(stats: &[RoomStats], timestamps: &Timestamps) {
+    let header = [
+        "ROOM",
+        "DOMAIN",
+        "MSGS",
+        "UNSEEN",
+        "FIRST JOINED",
+        "LAST JOINED",
+        "OLDEST MSG",
+        "NEWEST MSG",
+        "GAPS",
+        "SIZE",
+    ];
+
+    let rows = stats
+        .iter()
+        .map(|s| {
+            let (oldest, newest) = match s.covered {
+                Some((oldest, newest)) => (timestamps.format(oldest), timestamps.format(newest)),
+                None => ("-".to_string(), "-".to_string()),
+            };
+            [
+                format!("&{}", s.room.name),
+                s.room.domain.clone(),
+                s.msgs.to_string(),
+                s.unseen_msgs.to_string(),
+                timestamps.format(s.first_joined),
+                timestamps.format(s.last_joined),
+                oldest,
+                newest,
+                s.gaps.to_string(),
+                format_size(s.approx_size),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let mut widths = header.map(|h| h.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let header = header.map(|h| h.to_string());
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
+
+fn print_json(stats: &[RoomStats]) -> anyhow::Result<()> {
+    let rooms = stats
+        .iter()
+        .map(|s| {
+            json!({
+                "domain": s.room.domain,
+                "room": s.room.name,
+                "msgs": s.msgs,
+                "unseen_msgs": s.unseen_msgs,
+                "first_joined": s.first_joined.as_timestamp().to_string(),
+                "last_joined": s.last_joined.as_timestamp().to_string(),
+                "oldest_msg": s.covered.map(|(oldest, _)| oldest.as_timestamp().to_string()),
+                "newest_msg": s.covered.map(|(_, newest)| newest.as_timestamp().to_string()),
+                "gaps": s.gaps,
+                "approx_size": s.approx_size,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    println!("{}", serde_json::to_string_pretty(&rooms)?);
+    Ok(())
+}
+
+/// Show statistics about the rooms in the vault.
+///
+/// Times are displayed in the time zone `tz` unless a different time zone is
+/// specified in the args.
+pub async fn stats(vault: &EuphVault, args: Args, tz: TimeZone) -> anyhow::Result<()> {
+    let tz = match &args.time_zone {
+        Some(tz) => util::parse_time_zone(tz).context("failed to load time zone")?,
+        None => tz,
+    };
+    let timestamps = Timestamps::new(text::TIME_FORMAT.to_string(), tz)?;
+
+    let stats = vault
+        .room_stats()
+        .await?
+        .into_iter()
+        .filter(|s| args.domain.as_ref().is_none_or(|d| *d == s.room.domain))
+        .collect::<Vec<_>>();
+
+    match args.format {
+        Format::Table => print_table(&stats, &timestamps),
+        Format::Json => print_json(&stats)?,
+    }
+
+    Ok(())
+}
@@ -83,6 +83,10 @@ impl<M: Msg> Tree<M> {
         &self.root
     }
 
+    pub fn msgs(&self) -> impl Iterator<Item = &M> {
+        self.msgs.values()
+    }
+
     pub fn msg(&self, id: &M::Id) -> Option<&M> {
         self.msgs.get(id)
     }
@@ -13,6 +13,8 @@ use crate::{
 
 use super::UiError;
 
+pub use self::widgets::TIME_FORMAT;
+
 use self::{cursor::Cursor, tree::TreeViewState};
 
 mod blocks;
@@ -150,6 +152,22 @@ impl<M: Msg, S: MsgStore<M>> ChatState<M, S> {
         }
     }
 
+    /// Messages that were visible when the chat was last rendered.
+    pub fn visible_msgs(&self) -> &[M::Id] {
+        match self.mode {
+            Mode::Tree => self.tree.visible_msgs(),
+        }
+    }
+
+    /// Move the cursor to a message, unfolding its ancestors if necessary.
+    pub async fn jump_to_msg(&mut self, id: M::Id) -> Result<(), S::Error> {
+        match self.mode {
+            Mode::Tree => self.tree.unfold_ancestors(&id).await?,
+        }
+        self.cursor = Cursor::Msg(id);
+        Ok(())
+    }
+
     /// A [`Reaction::Composed`] message was sent successfully.
     pub fn send_successful(&mut self, id: M::Id) {
         if let Cursor::Pseudo { .. } = &self.cursor {
@@ -377,6 +377,18 @@ impl<M: Msg, S: MsgStore<M>> TreeViewState<M, S> {
         })
     }
 
+    pub fn visible_msgs(&self) -> &[M::Id] {
+        &self.last_visible_msgs
+    }
+
+    pub async fn unfold_ancestors(&mut self, id: &M::Id) -> Result<(), S::Error> {
+        let path = self.store.path(id).await?;
+        for segment in path.parent_segments() {
+            self.folded.remove(segment);
+        }
+        Ok(())
+    }
+
     pub fn send_successful(&mut self, id: &M::Id) {
         if let Cursor::Pseudo { .. } = self.last_cursor {
             self.last_cursor = Cursor::Msg(id.clone());
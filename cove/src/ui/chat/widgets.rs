@@ -46,7 +46,7 @@ impl<E> Widget<E> for Indent {
     }
 }
 
-const TIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+pub const TIME_FORMAT: &str = "%Y-%m-%d %H:%M";
 const TIME_WIDTH: u16 = 16;
 
 pub struct Time(Boxed<'static, Infallible>);
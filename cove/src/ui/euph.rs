@@ -1,8 +1,11 @@
 mod account;
 mod auth;
+mod bookmarks;
 mod inspect;
 mod links;
+mod moderate;
 mod nick;
 mod nick_list;
 mod popup;
 pub mod room;
+mod search;
@@ -0,0 +1,152 @@
+use cove_config::{Config, Keys};
+use cove_input::InputEvent;
+use crossterm::style::Stylize;
+use euphoxide::api::{MessageId, Time};
+use toss::{
+    Style, Styled, Widget, WidgetExt,
+    widgets::{EditorState, Join2, Text},
+};
+
+use crate::{
+    euph,
+    macros::logging_unwrap,
+    ui::{
+        UiError, key_bindings, util,
+        widgets::{ListBuilder, ListState, Popup},
+    },
+    vault::{Bookmark, EuphRoomVault, RoomIdentifier},
+};
+
+use super::popup::PopupResult;
+
+pub fn note_widget(editor: &mut EditorState) -> impl Widget<UiError> {
+    Popup::new(editor.widget(), "Bookmark note (optional)")
+}
+
+pub async fn handle_note_input_event(
+    event: &mut InputEvent<'_>,
+    keys: &Keys,
+    vault: &EuphRoomVault,
+    id: MessageId,
+    editor: &mut EditorState,
+) -> PopupResult {
+    if event.matches(&keys.general.abort) {
+        return PopupResult::Close;
+    }
+
+    if event.matches(&keys.general.confirm) {
+        let note = editor.text().trim();
+        let note = (!note.is_empty()).then(|| note.to_string());
+        logging_unwrap!(vault.add_bookmark(id, note, Time::now()).await);
+        return PopupResult::Close;
+    }
+
+    if util::handle_editor_input_event(editor, event, keys, |c| c != '\n') {
+        return PopupResult::Handled;
+    }
+
+    PopupResult::NotHandled
+}
+
+pub struct BookmarksState {
+    config: &'static Config,
+    bookmarks: Vec<Bookmark>,
+    list: ListState<(RoomIdentifier, MessageId)>,
+}
+
+impl BookmarksState {
+    pub fn new(config: &'static Config, bookmarks: Vec<Bookmark>) -> Self {
+        Self {
+            config,
+            bookmarks,
+            list: ListState::new(),
+        }
+    }
+
+    fn bookmark_styled(bookmark: &Bookmark, selected: bool) -> Styled {
+        let room = format!("&{} ", bookmark.room.name);
+        let nick = &bookmark.msg.sender.name;
+        let content = bookmark.msg.content.lines().next().unwrap_or_default();
+
+        let mut styled = if selected {
+            let style = Style::new().black().on_white();
+            Styled::new(room, style.bold())
+                .then(format!("[{nick}] "), style.bold())
+                .then(content, style)
+        } else {
+            Styled::new(room, Style::new().bold().blue())
+                .then_plain("[")
+                .and_then(euph::style_nick(nick, Style::new()))
+                .then_plain("] ")
+                .then_plain(content)
+        };
+
+        if let Some(note) = &bookmark.note {
+            let style = if selected {
+                Style::new().black().on_white().italic()
+            } else {
+                Style::new().grey().italic()
+            };
+            styled = styled.then_plain("\n").then(format!("  {note}"), style);
+        }
+
+        styled
+    }
+
+    pub fn widget(&mut self) -> impl Widget<UiError> {
+        let mut list_builder = ListBuilder::new();
+
+        if self.bookmarks.is_empty() {
+            list_builder.add_unsel(Text::new(("No bookmarks", Style::new().grey().italic())));
+        }
+
+        for bookmark in &self.bookmarks {
+            let bookmark = bookmark.clone();
+            let id = (bookmark.room.clone(), bookmark.msg.id);
+            list_builder.add_sel(id, move |selected| {
+                Text::new(Self::bookmark_styled(&bookmark, selected)).with_wrap(false)
+            });
+        }
+
+        let hint_style = Style::new().grey().italic();
+        let hint = Styled::new("Jump to message with ", hint_style)
+            .and_then(key_bindings::format_binding(
+                &self.config.keys.general.confirm,
+            ))
+            .then(".", hint_style);
+
+        Popup::new(
+            Join2::vertical(
+                list_builder.build(&mut self.list).segment(),
+                Text::new(hint)
+                    .padding()
+                    .with_top(1)
+                    .segment()
+                    .with_fixed(true),
+            ),
+            "Bookmarks",
+        )
+    }
+
+    pub fn handle_input_event(&mut self, event: &mut InputEvent<'_>, keys: &Keys) -> PopupResult {
+        if event.matches(&keys.general.abort) {
+            return PopupResult::Close;
+        }
+
+        if event.matches(&keys.general.confirm) {
+            if let Some((room, id)) = self.list.selected() {
+                return PopupResult::JumpToRoomMsg {
+                    room: room.clone(),
+                    id: *id,
+                };
+            }
+            return PopupResult::Handled;
+        }
+
+        if util::handle_list_input_event(&mut self.list, event, keys) {
+            return PopupResult::Handled;
+        }
+
+        PopupResult::NotHandled
+    }
+}
@@ -0,0 +1,256 @@
+use cove_config::{Config, Keys};
+use cove_input::InputEvent;
+use crossterm::style::Stylize;
+use euphoxide::api::{Message, MessageId, Snowflake, UserId};
+use toss::{
+    Style, Styled, Widget, WidgetExt,
+    widgets::{Join2, Text},
+};
+
+use crate::{
+    euph::Room,
+    ui::{
+        UiError, key_bindings, util,
+        widgets::{ListBuilder, ListState, Popup},
+    },
+};
+
+use super::popup::PopupResult;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    DeleteMessage,
+    Ban,
+    Unban,
+    GrantAccess,
+    RevokeAccess,
+    GrantManager,
+    RevokeManager,
+}
+
+impl Action {
+    fn description(self) -> &'static str {
+        match self {
+            Self::DeleteMessage => "Delete message",
+            Self::Ban => "Ban user",
+            Self::Unban => "Unban user",
+            Self::GrantAccess => "Grant room access",
+            Self::RevokeAccess => "Revoke room access",
+            Self::GrantManager => "Grant manager permissions",
+            Self::RevokeManager => "Revoke manager permissions",
+        }
+    }
+
+    fn destructive(self) -> bool {
+        match self {
+            Self::DeleteMessage | Self::Ban | Self::RevokeAccess | Self::RevokeManager => true,
+            Self::Unban | Self::GrantAccess | Self::GrantManager => false,
+        }
+    }
+}
+
+struct DeletableMessage {
+    id: MessageId,
+    parent: Option<MessageId>,
+    previous_edit_id: Option<Snowflake>,
+}
+
+pub struct ModerateState {
+    config: &'static Config,
+    name: String,
+    user_id: UserId,
+    msg: Option<DeletableMessage>,
+    actions: Vec<Action>,
+    list: ListState<usize>,
+    confirming: Option<Action>,
+}
+
+/// The account id of a user, if the user is logged in.
+fn account_id(user_id: &UserId) -> Option<Snowflake> {
+    user_id.0.strip_prefix("account:")?.parse().ok()
+}
+
+impl ModerateState {
+    fn new(
+        config: &'static Config,
+        name: String,
+        user_id: UserId,
+        msg: Option<DeletableMessage>,
+    ) -> Self {
+        let mut actions = vec![];
+        if msg.is_some() {
+            actions.push(Action::DeleteMessage);
+        }
+        actions.push(Action::Ban);
+        actions.push(Action::Unban);
+        if account_id(&user_id).is_some() {
+            actions.push(Action::GrantAccess);
+            actions.push(Action::RevokeAccess);
+            actions.push(Action::GrantManager);
+            actions.push(Action::RevokeManager);
+        }
+
+        Self {
+            config,
+            name,
+            user_id,
+            msg,
+            actions,
+            list: ListState::new(),
+            confirming: None,
+        }
+    }
+
+    pub fn for_session(config: &'static Config, name: String, user_id: UserId) -> Self {
+        Self::new(config, name, user_id, None)
+    }
+
+    pub fn for_message(config: &'static Config, msg: &Message) -> Self {
+        let deletable = DeletableMessage {
+            id: msg.id,
+            parent: msg.parent,
+            previous_edit_id: msg.previous_edit_id,
+        };
+        Self::new(
+            config,
+            msg.sender.name.clone(),
+            msg.sender.id.clone(),
+            Some(deletable),
+        )
+    }
+
+    fn target_styled(&self) -> Styled {
+        Styled::new(&self.name, Style::new().bold())
+            .then_plain(" (")
+            .then(&self.user_id.0, Style::new().grey())
+            .then_plain(")")
+    }
+
+    fn confirm_widget(&self, action: Action) -> Popup<Text> {
+        let warn_style = Style::new().bold().red();
+        let hint_style = Style::new().grey().italic();
+        let text = Styled::new(action.description(), Style::new().bold())
+            .then_plain(" for ")
+            .and_then(self.target_styled())
+            .then_plain("?\n\n")
+            .then("Confirm with ", hint_style)
+            .and_then(key_bindings::format_binding(
+                &self.config.keys.general.confirm,
+            ))
+            .then(", go back with ", hint_style)
+            .and_then(key_bindings::format_binding(
+                &self.config.keys.general.abort,
+            ))
+            .then(".", hint_style);
+
+        Popup::new(Text::new(text), "Confirm").with_border_style(warn_style)
+    }
+
+    pub fn widget(&mut self) -> impl Widget<UiError> {
+        if let Some(action) = self.confirming {
+            return self.confirm_widget(action).first2();
+        }
+
+        let style_selected = Style::new().black().on_white();
+
+        let mut list_builder = ListBuilder::new();
+        for (id, action) in self.actions.iter().enumerate() {
+            let action = *action;
+            list_builder.add_sel(id, move |selected| {
+                let style = match (selected, action.destructive()) {
+                    (true, _) => style_selected,
+                    (false, true) => Style::new().red(),
+                    (false, false) => Style::new(),
+                };
+                Text::new((action.description(), style)).with_wrap(false)
+            });
+        }
+
+        let title = self.target_styled();
+
+        Popup::new(
+            Join2::vertical(
+                Text::new(title)
+                    .padding()
+                    .with_bottom(1)
+                    .segment()
+                    .with_fixed(true),
+                list_builder.build(&mut self.list).segment(),
+            ),
+            "Moderate",
+        )
+        .second2()
+    }
+
+    fn run(&self, action: Action, room: &Room) {
+        let _ = match action {
+            Action::DeleteMessage => match &self.msg {
+                Some(msg) => room.delete_msg(msg.id, msg.parent, msg.previous_edit_id),
+                None => Ok(()),
+            },
+            Action::Ban => room.ban(self.user_id.clone()),
+            Action::Unban => room.unban(self.user_id.clone()),
+            Action::GrantAccess => account_id(&self.user_id)
+                .map(|id| room.grant_access(id))
+                .unwrap_or(Ok(())),
+            Action::RevokeAccess => account_id(&self.user_id)
+                .map(|id| room.revoke_access(id))
+                .unwrap_or(Ok(())),
+            Action::GrantManager => account_id(&self.user_id)
+                .map(|id| room.grant_manager(id))
+                .unwrap_or(Ok(())),
+            Action::RevokeManager => account_id(&self.user_id)
+                .map(|id| room.revoke_manager(id))
+                .unwrap_or(Ok(())),
+        };
+    }
+
+    pub fn handle_input_event(
+        &mut self,
+        event: &mut InputEvent<'_>,
+        keys: &Keys,
+        room: &Option<Room>,
+    ) -> PopupResult {
+        if let Some(action) = self.confirming {
+            if event.matches(&keys.general.abort) {
+                self.confirming = None;
+                return PopupResult::Handled;
+            }
+
+            if event.matches(&keys.general.confirm) {
+                if let Some(room) = room {
+                    self.run(action, room);
+                }
+                return PopupResult::Close;
+            }
+
+            return PopupResult::NotHandled;
+        }
+
+        if event.matches(&keys.general.abort) {
+            return PopupResult::Close;
+        }
+
+        if event.matches(&keys.general.confirm) {
+            let Some(action) = self.list.selected().and_then(|id| self.actions.get(*id)) else {
+                return PopupResult::Handled;
+            };
+
+            if action.destructive() {
+                self.confirming = Some(*action);
+                return PopupResult::Handled;
+            }
+
+            if let Some(room) = room {
+                self.run(*action, room);
+            }
+            return PopupResult::Close;
+        }
+
+        if util::handle_list_input_event(&mut self.list, event, keys) {
+            return PopupResult::Handled;
+        }
+
+        PopupResult::NotHandled
+    }
+}
@@ -1,16 +1,33 @@
 use std::io;
 
+use cove_config::Keys;
 use crossterm::style::Stylize;
+use euphoxide::api::{MessageId, Snowflake};
 use toss::{Style, Styled, Widget, widgets::Text};
 
-use crate::ui::{UiError, widgets::Popup};
+use crate::{
+    ui::{UiError, key_bindings, widgets::Popup},
+    vault::RoomIdentifier,
+};
 
 pub enum RoomPopup {
-    Error { description: String, reason: String },
+    Error {
+        description: String,
+        reason: String,
+    },
+    PmInvitation {
+        from_nick: String,
+        from_room: String,
+        pm_id: Snowflake,
+    },
+    PmInitiated {
+        to_nick: String,
+        pm_id: Snowflake,
+    },
 }
 
 impl RoomPopup {
-    fn server_error_widget(description: &str, reason: &str) -> impl Widget<UiError> + use<> {
+    fn server_error_widget(description: &str, reason: &str) -> Popup<Text> {
         let border_style = Style::new().red().bold();
         let text = Styled::new_plain(description)
             .then_plain("\n\n")
@@ -21,12 +38,50 @@ impl RoomPopup {
         Popup::new(Text::new(text), ("Error", border_style)).with_border_style(border_style)
     }
 
-    pub fn widget(&self) -> impl Widget<UiError> + use<> {
+    fn pm_widget(keys: &Keys, title: &str, text: Styled) -> Popup<Text> {
+        let text = text
+            .then_plain("\n\n")
+            .and_then(key_bindings::format_binding(&keys.general.confirm))
+            .then_plain(" to join the pm room, ")
+            .and_then(key_bindings::format_binding(&keys.general.abort))
+            .then_plain(" to dismiss");
+
+        Popup::new(Text::new(text), title.to_string())
+    }
+
+    pub fn widget(&self, keys: &Keys) -> impl Widget<UiError> + use<> {
         match self {
             Self::Error {
                 description,
                 reason,
             } => Self::server_error_widget(description, reason),
+            Self::PmInvitation {
+                from_nick,
+                from_room,
+                ..
+            } => {
+                let text = Styled::new(from_nick, Style::new().bold())
+                    .then_plain(" invited you to a pm from ")
+                    .then(format!("&{from_room}"), Style::new().bold())
+                    .then_plain(".");
+                Self::pm_widget(keys, "PM invitation", text)
+            }
+            Self::PmInitiated { to_nick, .. } => {
+                let text = Styled::new_plain("Created a pm with ")
+                    .then(to_nick, Style::new().bold())
+                    .then_plain(".");
+                Self::pm_widget(keys, "PM created", text)
+            }
+        }
+    }
+
+    /// Name of the room that can be joined from this popup, if any.
+    pub fn room_name(&self) -> Option<String> {
+        match self {
+            Self::Error { .. } => None,
+            Self::PmInvitation { pm_id, .. } | Self::PmInitiated { pm_id, .. } => {
+                Some(format!("pm:{pm_id}"))
+            }
         }
     }
 }
@@ -37,4 +92,6 @@ pub enum PopupResult {
     Close,
     SwitchToRoom { name: String },
     ErrorOpeningLink { link: String, error: io::Error },
+    JumpToMsg { id: MessageId },
+    JumpToRoomMsg { room: RoomIdentifier, id: MessageId },
 }
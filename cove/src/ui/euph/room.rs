@@ -32,10 +32,14 @@ use crate::{
 
 use super::{
     account::AccountUiState,
-    auth, inspect,
+    auth,
+    bookmarks::{self, BookmarksState},
+    inspect,
     links::LinksState,
+    moderate::ModerateState,
     nick, nick_list,
     popup::{PopupResult, RoomPopup},
+    search::SearchState,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -50,6 +54,10 @@ enum State {
     Nick(EditorState),
     Account(AccountUiState),
     Links(LinksState),
+    Moderate(ModerateState),
+    Search(SearchState),
+    Bookmark(MessageId, EditorState),
+    Bookmarks(BookmarksState),
     InspectMessage(Message),
     InspectSession(SessionInfo),
 }
@@ -74,6 +82,12 @@ pub struct EuphRoom {
     nick_list: ListState<SessionId>,
 
     mentioned: bool,
+
+    tz: TimeZone,
+
+    /// Amount of gaps in the room history. Only updated when the room's spans
+    /// change so the rooms list doesn't need to query the vault.
+    gaps: usize,
 }
 
 impl EuphRoom {
@@ -94,10 +108,15 @@ impl EuphRoom {
             focus: Focus::Chat,
             state: State::Normal,
             popups: VecDeque::new(),
-            chat: ChatState::new(vault, tz),
+            chat: ChatState::new(
+                vault.with_hide_deleted(config.hide_deleted_messages),
+                tz.clone(),
+            ),
             last_msg_sent: None,
             nick_list: ListState::new(),
             mentioned: false,
+            tz,
+            gaps: 0,
         }
     }
 
@@ -191,6 +210,15 @@ impl EuphRoom {
         logging_unwrap!(self.vault().unseen_msgs_count().await)
     }
 
+    /// Amount of gaps in the room history that are currently being backfilled.
+    pub fn backfill_gaps(&self) -> usize {
+        let connected = self.room_state().and_then(|s| s.conn()).is_some();
+        if !connected || self.vault().vault().vault().ephemeral() {
+            return 0;
+        }
+        self.gaps
+    }
+
     async fn stabilize_pseudo_msg(&mut self) {
         if let Some(id_rx) = &mut self.last_msg_sent {
             match id_rx.try_recv() {
@@ -237,6 +265,15 @@ impl EuphRoom {
             ) => {}
             (State::Nick(_), _) => self.state = State::Normal,
 
+            (
+                State::Moderate(_),
+                Some(euph::State::Connected {
+                    state: client::State::Joined(_),
+                    ..
+                }),
+            ) => {}
+            (State::Moderate(_), _) => self.state = State::Normal,
+
             (State::Account(account), state) => {
                 #[expect(clippy::collapsible_match)] // State is being modified
                 if !account.stabilize(state) {
@@ -248,8 +285,32 @@ impl EuphRoom {
         }
     }
 
+    async fn stabilize_untruncation(&mut self) {
+        // The inspect popup should show whether the message is still truncated
+        if let State::InspectMessage(msg) = &mut self.state
+            && msg.truncated
+            && let Some(updated) = logging_unwrap!(self.chat.store().full_msg(msg.id).await)
+        {
+            *msg = updated;
+        }
+
+        let Some(room) = &self.room else {
+            return;
+        };
+
+        let mut ids = vec![];
+        if let State::InspectMessage(msg) = &self.state
+            && msg.truncated
+        {
+            ids.push(msg.id);
+        }
+        ids.extend_from_slice(self.chat.visible_msgs());
+        room.set_visible_msgs(ids);
+    }
+
     async fn stabilize(&mut self) {
         self.stabilize_pseudo_msg().await;
+        self.stabilize_untruncation().await;
         self.stabilize_focus();
         self.stabilize_state();
     }
@@ -278,6 +339,12 @@ impl EuphRoom {
             State::Nick(editor) => layers.push(nick::widget(editor).desync().boxed_async()),
             State::Account(account) => layers.push(account.widget().desync().boxed_async()),
             State::Links(links) => layers.push(links.widget().desync().boxed_async()),
+            State::Moderate(moderate) => layers.push(moderate.widget().desync().boxed_async()),
+            State::Search(search) => layers.push(search.widget().desync().boxed_async()),
+            State::Bookmark(_, editor) => {
+                layers.push(bookmarks::note_widget(editor).desync().boxed_async())
+            }
+            State::Bookmarks(bookmarks) => layers.push(bookmarks.widget().desync().boxed_async()),
             State::InspectMessage(message) => {
                 layers.push(inspect::message_widget(message).desync().boxed_async())
             }
@@ -287,7 +354,7 @@ impl EuphRoom {
         }
 
         for popup in &self.popups {
-            layers.push(popup.widget().desync().boxed_async());
+            layers.push(popup.widget(&self.config.keys).desync().boxed_async());
         }
 
         Layer::new(layers).boxed_async()
@@ -445,6 +512,12 @@ impl EuphRoom {
                     self.state = State::Account(AccountUiState::new());
                     return true;
                 }
+                if event.matches(&keys.room.action.resync) {
+                    if let Some(room) = &mut self.room {
+                        let _ = room.resync();
+                    }
+                    return true;
+                }
             }
 
             // Otherwise
@@ -487,6 +560,39 @@ impl EuphRoom {
             return true;
         }
 
+        if event.matches(&keys.room.action.moderate) {
+            if self
+                .room_state_joined()
+                .is_some_and(|j| j.session.is_manager)
+                && let Some(id) = self.chat.cursor()
+                && let Some(msg) = logging_unwrap!(self.vault().full_msg(*id).await)
+            {
+                self.state = State::Moderate(ModerateState::for_message(self.config, &msg));
+            }
+            return true;
+        }
+
+        if event.matches(&keys.tree.action.toggle_bookmark) {
+            if let Some(id) = self.chat.cursor() {
+                let id = *id;
+                if !logging_unwrap!(self.vault().remove_bookmark(id).await) {
+                    self.state = State::Bookmark(id, EditorState::new());
+                }
+            }
+            return true;
+        }
+
+        if event.matches(&keys.room.action.search) {
+            self.state = State::Search(SearchState::new(self.config, self.tz.clone()));
+            return true;
+        }
+
+        if event.matches(&keys.room.action.bookmarks) {
+            let bookmarks = logging_unwrap!(self.vault().vault().bookmarks().await);
+            self.state = State::Bookmarks(BookmarksState::new(self.config, bookmarks));
+            return true;
+        }
+
         false
     }
 
@@ -512,6 +618,37 @@ impl EuphRoom {
             return true;
         }
 
+        if event.matches(&keys.room.action.pm) {
+            if let Some(room) = &self.room
+                && let Some(joined) = room.state().joined()
+                && let Some(id) = self.nick_list.selected()
+                && let Some(session) = joined.listing.get(id)
+            {
+                let user_id = match session {
+                    SessionInfo::Full(session) => session.id.clone(),
+                    SessionInfo::Partial(event) => event.id.clone(),
+                };
+                let _ = room.pm_initiate(user_id);
+            }
+            return true;
+        }
+
+        if event.matches(&keys.room.action.moderate) {
+            if let Some(joined) = self.room_state_joined()
+                && joined.session.is_manager
+                && let Some(id) = self.nick_list.selected()
+                && let Some(session) = joined.listing.get(id)
+            {
+                let (name, user_id) = match session {
+                    SessionInfo::Full(session) => (session.name.clone(), session.id.clone()),
+                    SessionInfo::Partial(event) => (event.to.clone(), event.id.clone()),
+                };
+                self.state =
+                    State::Moderate(ModerateState::for_session(self.config, name, user_id));
+            }
+            return true;
+        }
+
         false
     }
 
@@ -547,11 +684,22 @@ impl EuphRoom {
         event: &mut InputEvent<'_>,
         keys: &Keys,
     ) -> RoomResult {
-        if !self.popups.is_empty() {
+        if let Some(popup) = self.popups.back() {
             if event.matches(&keys.general.abort) {
                 self.popups.pop_back();
                 return RoomResult::Handled;
             }
+            if event.matches(&keys.general.confirm)
+                && let Some(name) = popup.room_name()
+            {
+                self.popups.pop_back();
+                return RoomResult::SwitchToRoom {
+                    room: RoomIdentifier {
+                        domain: self.domain().to_string(),
+                        name,
+                    },
+                };
+            }
             // Prevent event from reaching anything below the popup
             return RoomResult::NotHandled;
         }
@@ -562,6 +710,16 @@ impl EuphRoom {
             State::Nick(editor) => nick::handle_input_event(event, keys, &self.room, editor),
             State::Account(account) => account.handle_input_event(event, keys, &self.room),
             State::Links(links) => links.handle_input_event(event, keys),
+            State::Moderate(moderate) => moderate.handle_input_event(event, keys, &self.room),
+            State::Search(search) => {
+                let vault = self.chat.store();
+                search.handle_input_event(event, keys, vault).await
+            }
+            State::Bookmark(id, editor) => {
+                let vault = self.chat.store();
+                bookmarks::handle_note_input_event(event, keys, vault, *id, editor).await
+            }
+            State::Bookmarks(bookmarks) => bookmarks.handle_input_event(event, keys),
             State::InspectMessage(_) | State::InspectSession(_) => {
                 inspect::handle_input_event(event, keys)
             }
@@ -587,9 +745,29 @@ impl EuphRoom {
                 });
                 RoomResult::Handled
             }
+            PopupResult::JumpToMsg { id } => {
+                self.state = State::Normal;
+                logging_unwrap!(self.chat.jump_to_msg(id).await);
+                RoomResult::Handled
+            }
+            PopupResult::JumpToRoomMsg { room, id } => {
+                self.state = State::Normal;
+                if room == *self.vault().room() {
+                    logging_unwrap!(self.chat.jump_to_msg(id).await);
+                    RoomResult::Handled
+                } else {
+                    RoomResult::JumpToMsg { room, id }
+                }
+            }
         }
     }
 
+    pub async fn jump_to_msg(&mut self, id: MessageId) {
+        self.state = State::Normal;
+        self.focus = Focus::Chat;
+        logging_unwrap!(self.chat.jump_to_msg(id).await);
+    }
+
     pub async fn handle_event(&mut self, client_id: usize, event: ClientEvent) -> bool {
         let Some(room) = &self.room else { return false };
 
@@ -626,6 +804,18 @@ impl EuphRoom {
             }
         }
 
+        // Only snapshots and log replies add spans that can change the gaps
+        let spans_changed = matches!(
+            &event,
+            ClientEvent::Packet {
+                packet: ParsedPacket {
+                    content: Ok(Data::SnapshotEvent(_) | Data::LogReply(_)),
+                    ..
+                },
+                ..
+            }
+        );
+
         // We handle the packet internally first because the room event handling
         // will consume it while we only need a reference.
         let handled = if let ClientEvent::Packet { packet, .. } = &event {
@@ -645,6 +835,10 @@ impl EuphRoom {
             .handle_event(event)
             .await;
 
+        if spans_changed {
+            self.gaps = logging_unwrap!(self.vault().gaps().await).len();
+        }
+
         handled
     }
 
@@ -673,6 +867,19 @@ impl EuphRoom {
             Data::LoginReply(reply) if !reply.success => Some(("login", reply.reason.clone())),
             _ => None,
         };
+
+        match data {
+            Data::PmInitiateEvent(event) => self.popups.push_front(RoomPopup::PmInvitation {
+                from_nick: event.from_nick.clone(),
+                from_room: event.from_room.clone(),
+                pm_id: event.pm_id,
+            }),
+            Data::PmInitiateReply(reply) => self.popups.push_front(RoomPopup::PmInitiated {
+                to_nick: reply.to_nick.clone(),
+                pm_id: reply.pm_id,
+            }),
+            _ => {}
+        }
         if let Some((action, reason)) = error {
             let description = format!("Failed to {action}.");
             let reason = reason.unwrap_or_else(|| "no idea, the server wouldn't say".to_string());
@@ -721,6 +928,7 @@ pub enum RoomResult {
     NotHandled,
     Handled,
     SwitchToRoom { room: RoomIdentifier },
+    JumpToMsg { room: RoomIdentifier, id: MessageId },
 }
 
 impl From<bool> for RoomResult {
@@ -0,0 +1,184 @@
+use cove_config::{Config, Keys};
+use cove_input::InputEvent;
+use crossterm::style::Stylize;
+use euphoxide::api::MessageId;
+use jiff::tz::TimeZone;
+use toss::{
+    Style, Styled, Widget, WidgetExt,
+    widgets::{EditorState, Join3, Text},
+};
+
+use crate::{
+    euph::{self, SmallMessage},
+    macros::logging_unwrap,
+    ui::{
+        UiError,
+        chat::TIME_FORMAT,
+        key_bindings, util,
+        widgets::{ListBuilder, ListState, Popup},
+    },
+    vault::EuphRoomVault,
+};
+
+use super::popup::PopupResult;
+
+/// Maximum amount of search results displayed at once.
+const MAX_RESULTS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Query,
+    Results,
+}
+
+pub struct SearchState {
+    config: &'static Config,
+    tz: TimeZone,
+    focus: Focus,
+    query: EditorState,
+    results: Option<Vec<SmallMessage>>,
+    list: ListState<MessageId>,
+}
+
+impl SearchState {
+    pub fn new(config: &'static Config, tz: TimeZone) -> Self {
+        Self {
+            config,
+            tz,
+            focus: Focus::Query,
+            query: EditorState::new(),
+            results: None,
+            list: ListState::new(),
+        }
+    }
+
+    fn result_styled(msg: &SmallMessage, tz: &TimeZone, selected: bool) -> Styled {
+        let time = msg.time.as_timestamp().to_zoned(tz.clone());
+        let time = format!("{} ", time.strftime(TIME_FORMAT));
+        let content = msg.content.lines().next().unwrap_or_default();
+        if selected {
+            let style = Style::new().black().on_white();
+            Styled::new(time, style)
+                .then(format!("[{}] ", msg.nick), style.bold())
+                .then(content, style)
+        } else {
+            Styled::new(time, Style::new().grey())
+                .then_plain("[")
+                .and_then(euph::style_nick(&msg.nick, Style::new()))
+                .then_plain("] ")
+                .then_plain(content)
+        }
+    }
+
+    pub fn widget(&mut self) -> impl Widget<UiError> {
+        let mut list_builder = ListBuilder::new();
+
+        match &self.results {
+            None => {}
+            Some(results) if results.is_empty() => list_builder.add_unsel(Text::new((
+                "No messages found",
+                Style::new().grey().italic(),
+            ))),
+            Some(results) => {
+                for msg in results {
+                    let msg = msg.clone();
+                    let tz = self.tz.clone();
+                    list_builder.add_sel(msg.id, move |selected| {
+                        Text::new(Self::result_styled(&msg, &tz, selected)).with_wrap(false)
+                    });
+                }
+            }
+        }
+
+        let hint_style = Style::new().grey().italic();
+        let hint = match self.focus {
+            Focus::Query => Styled::new("Search with ", hint_style).and_then(
+                key_bindings::format_binding(&self.config.keys.general.confirm),
+            ),
+            Focus::Results => Styled::new("Jump to message with ", hint_style).and_then(
+                key_bindings::format_binding(&self.config.keys.general.confirm),
+            ),
+        }
+        .then(", switch focus with ", hint_style)
+        .and_then(key_bindings::format_binding(
+            &self.config.keys.general.focus,
+        ))
+        .then(".", hint_style);
+
+        Popup::new(
+            Join3::vertical(
+                self.query
+                    .widget()
+                    .with_focus(self.focus == Focus::Query)
+                    .padding()
+                    .with_bottom(1)
+                    .segment()
+                    .with_fixed(true),
+                list_builder.build(&mut self.list).segment(),
+                Text::new(hint)
+                    .padding()
+                    .with_top(1)
+                    .segment()
+                    .with_fixed(true),
+            ),
+            "Search",
+        )
+    }
+
+    async fn search(&mut self, vault: &EuphRoomVault) {
+        let query = self.query.text().to_string();
+        let results = logging_unwrap!(vault.search_msgs(query, MAX_RESULTS).await);
+
+        if !results.is_empty() {
+            self.focus = Focus::Results;
+        }
+        self.results = Some(results);
+        self.list = ListState::new();
+    }
+
+    pub async fn handle_input_event(
+        &mut self,
+        event: &mut InputEvent<'_>,
+        keys: &Keys,
+        vault: &EuphRoomVault,
+    ) -> PopupResult {
+        if event.matches(&keys.general.abort) {
+            return PopupResult::Close;
+        }
+
+        if event.matches(&keys.general.focus) {
+            self.focus = match self.focus {
+                Focus::Query => Focus::Results,
+                Focus::Results => Focus::Query,
+            };
+            return PopupResult::Handled;
+        }
+
+        match self.focus {
+            Focus::Query => {
+                if event.matches(&keys.general.confirm) {
+                    self.search(vault).await;
+                    return PopupResult::Handled;
+                }
+
+                if util::handle_editor_input_event(&mut self.query, event, keys, |c| c != '\n') {
+                    return PopupResult::Handled;
+                }
+            }
+            Focus::Results => {
+                if event.matches(&keys.general.confirm) {
+                    if let Some(id) = self.list.selected() {
+                        return PopupResult::JumpToMsg { id: *id };
+                    }
+                    return PopupResult::Handled;
+                }
+
+                if util::handle_list_input_event(&mut self.list, event, keys) {
+                    return PopupResult::Handled;
+                }
+            }
+        }
+
+        PopupResult::NotHandled
+    }
+}
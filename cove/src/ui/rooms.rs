@@ -1,7 +1,6 @@
 use std::{
     collections::{HashMap, HashSet, hash_map::Entry},
     iter,
-    sync::{Arc, Mutex},
 };
 
 use cove_config::{Config, Keys, RoomsSortOrder};
@@ -69,11 +68,9 @@ struct EuphServer {
 }
 
 impl EuphServer {
-    async fn new(vault: &EuphVault, domain: String) -> Self {
+    async fn new(config: &Config, vault: &EuphVault, domain: String) -> Self {
         let cookies = logging_unwrap!(vault.cookies(domain.clone()).await);
-        let mut config = ServerConfig::default();
-        config.client.url = format!("https://{domain}/");
-        config.cookies = Arc::new(Mutex::new(cookies));
+        let config = euph::server_config(config, &domain, cookies);
 
         Self {
             config,
@@ -134,6 +131,7 @@ impl Rooms {
     }
 
     async fn get_or_insert_server<'a>(
+        config: &Config,
         vault: &Vault,
         euph_servers: &'a mut HashMap<String, EuphServer>,
         domain: String,
@@ -141,16 +139,20 @@ impl Rooms {
         match euph_servers.entry(domain.clone()) {
             Entry::Occupied(entry) => entry.into_mut(),
             Entry::Vacant(entry) => {
-                let server = EuphServer::new(&vault.euph(), domain).await;
+                let server = EuphServer::new(config, &vault.euph(), domain).await;
                 entry.insert(server)
             }
         }
     }
 
     async fn get_or_insert_room(&mut self, room: RoomIdentifier) -> &mut EuphRoom {
-        let server =
-            Self::get_or_insert_server(&self.vault, &mut self.euph_servers, room.domain.clone())
-                .await;
+        let server = Self::get_or_insert_server(
+            self.config,
+            &self.vault,
+            &mut self.euph_servers,
+            room.domain.clone(),
+        )
+        .await;
 
         self.euph_rooms.entry(room.clone()).or_insert_with(|| {
             EuphRoom::new(
@@ -165,9 +167,13 @@ impl Rooms {
     }
 
     async fn connect_to_room(&mut self, room: RoomIdentifier) {
-        let server =
-            Self::get_or_insert_server(&self.vault, &mut self.euph_servers, room.domain.clone())
-                .await;
+        let server = Self::get_or_insert_server(
+            self.config,
+            &self.vault,
+            &mut self.euph_servers,
+            room.domain.clone(),
+        )
+        .await;
 
         let room = self.euph_rooms.entry(room.clone()).or_insert_with(|| {
             EuphRoom::new(
@@ -185,9 +191,13 @@ impl Rooms {
 
     async fn connect_to_all_rooms(&mut self) {
         for (id, room) in &mut self.euph_rooms {
-            let server =
-                Self::get_or_insert_server(&self.vault, &mut self.euph_servers, id.domain.clone())
-                    .await;
+            let server = Self::get_or_insert_server(
+                self.config,
+                &self.vault,
+                &mut self.euph_servers,
+                id.domain.clone(),
+            )
+            .await;
 
             room.connect(&mut server.next_instance_id);
         }
@@ -349,7 +359,15 @@ impl Rooms {
         result.join(" ")
     }
 
-    fn format_room_state(state: Option<&euph::State>) -> Option<String> {
+    fn format_backfill(gaps: usize) -> Option<String> {
+        match gaps {
+            0 => None,
+            1 => Some("backfilling 1 gap".to_string()),
+            n => Some(format!("backfilling {n} gaps")),
+        }
+    }
+
+    fn format_room_state(state: Option<&euph::State>, gaps: usize) -> Option<String> {
         match state {
             None | Some(euph::State::Stopped) => None,
             Some(euph::State::Disconnected) => Some("waiting".to_string()),
@@ -359,7 +377,13 @@ impl Rooms {
                     Some("auth required".to_string())
                 }
                 client::State::Joining(_) => Some("joining".to_string()),
-                client::State::Joined(joined) => Some(Self::format_pbln(joined)),
+                client::State::Joined(joined) => {
+                    let pbln = Self::format_pbln(joined);
+                    Some(match Self::format_backfill(gaps) {
+                        Some(backfill) => format!("{pbln}, {backfill}"),
+                        None => pbln,
+                    })
+                }
             },
         }
     }
@@ -372,10 +396,10 @@ impl Rooms {
         }
     }
 
-    fn format_room_info(state: Option<&euph::State>, unseen: usize) -> Styled {
+    fn format_room_info(state: Option<&euph::State>, gaps: usize, unseen: usize) -> Styled {
         let unseen_style = Style::new().bold().green();
 
-        let state = Self::format_room_state(state);
+        let state = Self::format_room_state(state, gaps);
         let unseen = Self::format_unseen_msgs(unseen);
 
         match (state, unseen) {
@@ -413,8 +437,9 @@ impl Rooms {
         }
         Self::sort_rooms(&mut rooms, order);
         for (id, state, unseen) in rooms {
+            let gaps = euph_rooms[id].backfill_gaps();
             let id = id.clone();
-            let info = Self::format_room_info(state, unseen);
+            let info = Self::format_room_info(state, gaps, unseen);
             list_builder.add_sel(id.clone(), move |selected| {
                 let domain_style = if selected {
                     Style::new().black().on_white()
@@ -466,7 +491,12 @@ impl Rooms {
             .iter()
             .filter(|r| r.1.room_state().is_some())
             .count();
-        let total_unseen = logging_unwrap!(vault.euph().total_unseen_msgs_count().await);
+        let total_unseen = logging_unwrap!(
+            vault
+                .euph()
+                .total_unseen_msgs_count(config.hide_deleted_messages)
+                .await
+        );
         if total_unseen > 0 {
             heading = heading
                 .then_plain(format!(" ({connected_rooms}/{total_rooms}, "))
@@ -594,6 +624,15 @@ impl Rooms {
                             self.state = State::ShowRoom(room);
                             return true;
                         }
+                        RoomResult::JumpToMsg { room, id } => {
+                            self.list.move_cursor_to_id(&room);
+                            self.get_or_insert_room(room.clone())
+                                .await
+                                .jump_to_msg(id)
+                                .await;
+                            self.state = State::ShowRoom(room);
+                            return true;
+                        }
                     }
                     if event.matches(&keys.general.abort) {
                         self.state = State::ShowList;
@@ -32,6 +32,12 @@ pub fn load_time_zone(tz_string: Option<&str>) -> Result<TimeZone, jiff::Error>
         return Ok(TimeZone::system());
     };
 
+    parse_time_zone(tz_string)
+}
+
+/// Load a [`TimeZone`] from a string in the format that the `TZ` environment
+/// variable uses.
+pub fn parse_time_zone(tz_string: &str) -> Result<TimeZone, jiff::Error> {
     if tz_string == "localtime" {
         return Ok(TimeZone::system());
     }
@@ -1,18 +1,28 @@
-use std::{fs, path::Path};
-
-use rusqlite::Connection;
+use std::{
+    fs::{self, File, TryLockError},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use rusqlite::{Connection, DatabaseName, OpenFlags};
 use vault::{Action, tokio::TokioVault};
 
-pub use self::euph::{EuphRoomVault, EuphVault, RoomIdentifier};
+pub use self::euph::{
+    Bookmark, EuphRoomVault, EuphVault, FsckReport, MergeSeen, MsgFilter, RoomIdentifier, RoomStats,
+};
 
 mod euph;
 mod migrate;
-mod prepare;
 
 #[derive(Debug, Clone)]
 pub struct Vault {
     tokio_vault: TokioVault,
-    ephemeral: bool,
+    /// Path of the database file, if the vault is not in memory.
+    path: Option<PathBuf>,
+    /// Held for as long as the vault is open so only one cove instance at a
+    /// time can write to the vault.
+    _lock: Option<Arc<File>>,
 }
 
 struct GcAction;
@@ -22,13 +32,41 @@ impl Action for GcAction {
     type Error = rusqlite::Error;
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
-        conn.execute_batch("ANALYZE; VACUUM;")
+        conn.execute_batch("ANALYZE; VACUUM;")?;
+
+        // Vacuuming may change the rowids of euph_msgs, which the full-text
+        // index refers to.
+        conn.execute_batch("INSERT INTO euph_msgs_fts (euph_msgs_fts) VALUES ('rebuild');")
     }
 }
 
+/// Copy the vault at `path` to `dest` using a separate read-only connection.
+///
+/// Since the vault uses WAL mode, this doesn't block the vault's own connection
+/// while the backup is running.
+fn backup_to(path: &Path, dest: &Path) -> rusqlite::Result<()> {
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    let conn = Connection::open_with_flags(path, flags)?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+
+    // The backup is written to a temporary file first so that an interrupted
+    // backup never leaves behind a partial database under the final name.
+    let mut tmp_path = dest.to_path_buf().into_os_string();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    conn.backup(DatabaseName::Main, &tmp_path, None)?;
+    fs::rename(&tmp_path, dest).map_err(|err| {
+        rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CANTOPEN),
+            Some(format!("failed to move backup into place: {err}")),
+        )
+    })
+}
+
 impl Vault {
     pub fn ephemeral(&self) -> bool {
-        self.ephemeral
+        self.path.is_none()
     }
 
     pub async fn close(&self) {
@@ -39,41 +77,100 @@ impl Vault {
         self.tokio_vault.execute(GcAction).await
     }
 
+    /// Write a consistent snapshot of the vault to a file.
+    pub async fn backup(&self, dest: PathBuf) -> anyhow::Result<()> {
+        let Some(path) = self.path.clone() else {
+            anyhow::bail!("an ephemeral vault can't be backed up");
+        };
+        tokio::task::spawn_blocking(move || backup_to(&path, &dest)).await??;
+        Ok(())
+    }
+
     pub fn euph(&self) -> EuphVault {
         EuphVault::new(self.clone())
     }
 }
 
-fn launch_from_connection(conn: Connection, ephemeral: bool) -> rusqlite::Result<Vault> {
+fn launch_from_connection(
+    conn: Connection,
+    path: Option<&Path>,
+    lock: Option<File>,
+) -> rusqlite::Result<Vault> {
     conn.pragma_update(None, "foreign_keys", true)?;
     conn.pragma_update(None, "trusted_schema", false)?;
 
-    let tokio_vault = TokioVault::launch_and_prepare(conn, &migrate::MIGRATIONS, prepare::prepare)?;
+    let tokio_vault = TokioVault::launch(conn, &migrate::MIGRATIONS)?;
     Ok(Vault {
         tokio_vault,
-        ephemeral,
+        path: path.map(|path| path.to_path_buf()),
+        _lock: lock.map(Arc::new),
     })
 }
 
+fn lock_error(msg: String) -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(
+        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY),
+        Some(msg),
+    )
+}
+
+/// Acquire the lock that prevents multiple cove instances from writing to the
+/// same vault at the same time.
+fn lock(path: &Path) -> rusqlite::Result<File> {
+    let mut lock_path = path.to_path_buf().into_os_string();
+    lock_path.push(".lock");
+
+    let file = File::create(&lock_path)
+        .map_err(|err| lock_error(format!("failed to open vault lock file: {err}")))?;
+
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(lock_error(
+            "vault is already in use by another cove instance".to_string(),
+        )),
+        Err(TryLockError::Error(err)) => Err(lock_error(format!("failed to lock vault: {err}"))),
+    }
+}
+
 pub fn launch(path: &Path) -> rusqlite::Result<Vault> {
     // If this fails, rusqlite will complain about not being able to open the db
     // file, which saves me from adding a separate vault error type.
     let _ = fs::create_dir_all(path.parent().expect("path to file"));
 
+    let lock = lock(path)?;
     let conn = Connection::open(path)?;
 
-    // Setting locking mode before journal mode so no shared memory files
-    // (*-shm) need to be created by sqlite. Apparently, setting the journal
-    // mode is also enough to immediately acquire the exclusive lock even if the
-    // database was already using WAL.
-    // https://sqlite.org/pragma.html#pragma_locking_mode
-    conn.pragma_update(None, "locking_mode", "exclusive")?;
+    // The vault is not locked exclusively by sqlite so that read-only
+    // instances can access it at the same time. Instead, the lock file
+    // prevents multiple writing instances.
     conn.pragma_update(None, "journal_mode", "wal")?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+
+    launch_from_connection(conn, Some(path), Some(lock))
+}
+
+/// Open an existing vault without writing to it.
+///
+/// This works even while another cove instance is using the vault.
+pub fn launch_read_only(path: &Path) -> rusqlite::Result<Vault> {
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    let conn = Connection::open_with_flags(path, flags)?;
+    conn.pragma_update(None, "query_only", true)?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+
+    // Migrations can't be applied to a read-only connection
+    let version = conn.query_row("PRAGMA user_version", [], |row| row.get::<_, usize>(0))?;
+    if version < migrate::MIGRATIONS.len() {
+        return Err(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_READONLY),
+            Some("vault needs to be migrated, run cove once to migrate it".to_string()),
+        ));
+    }
 
-    launch_from_connection(conn, false)
+    launch_from_connection(conn, Some(path), None)
 }
 
 pub fn launch_in_memory() -> rusqlite::Result<Vault> {
     let conn = Connection::open_in_memory()?;
-    launch_from_connection(conn, true)
+    launch_from_connection(conn, None, None)
 }
@@ -1,4 +1,4 @@
-use std::{fmt, mem, str::FromStr};
+use std::{collections::HashMap, fmt, mem, path::PathBuf, str::FromStr};
 
 use async_trait::async_trait;
 use cookie::{Cookie, CookieJar};
@@ -46,6 +46,34 @@ impl FromSql for WTime {
     }
 }
 
+/// Convert a row whose first columns are the message columns of `euph_msgs`,
+/// in the order `id, parent, previous_edit_id, time, content, ...` used
+/// throughout this file, into a [`Message`].
+fn row_to_msg(row: &Row<'_>) -> rusqlite::Result<Message> {
+    Ok(Message {
+        id: MessageId(row.get::<_, WSnowflake>(0)?.0),
+        parent: row.get::<_, Option<WSnowflake>>(1)?.map(|s| MessageId(s.0)),
+        previous_edit_id: row.get::<_, Option<WSnowflake>>(2)?.map(|s| s.0),
+        time: row.get::<_, WTime>(3)?.0,
+        content: row.get(4)?,
+        encryption_key_id: row.get(5)?,
+        edited: row.get::<_, Option<WTime>>(6)?.map(|t| t.0),
+        deleted: row.get::<_, Option<WTime>>(7)?.map(|t| t.0),
+        truncated: row.get(8)?,
+        sender: SessionView {
+            id: UserId(row.get(9)?),
+            name: row.get(10)?,
+            server_id: row.get(11)?,
+            server_era: row.get(12)?,
+            session_id: SessionId(row.get(13)?),
+            is_staff: row.get(14)?,
+            is_manager: row.get(15)?,
+            client_address: row.get(16)?,
+            real_client_address: row.get(17)?,
+        },
+    })
+}
+
 #[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct RoomIdentifier {
     pub domain: String,
@@ -86,6 +114,7 @@ impl EuphVault {
         EuphRoomVault {
             vault: self.clone(),
             room,
+            hide_deleted: false,
         }
     }
 }
@@ -115,7 +144,79 @@ euph_vault_actions! {
     SetCookies : set_cookies(domain: String, cookies: CookieJar) -> ();
     ClearCookies : clear_cookies(domain: Option<String>) -> ();
     GetRooms : rooms() -> Vec<RoomIdentifier>;
-    GetTotalUnseenMsgsCount : total_unseen_msgs_count() -> usize;
+    GetTotalUnseenMsgsCount : total_unseen_msgs_count(hide_deleted: bool) -> usize;
+    Merge : merge(path: PathBuf, seen: MergeSeen) -> usize;
+    Fsck : fsck(fix: bool) -> Vec<FsckReport>;
+    GetRoomStats : room_stats() -> Vec<RoomStats>;
+    MoveRooms : move_rooms(from: String, to: String, room: Option<String>) -> usize;
+    GetBookmarks : bookmarks() -> Vec<Bookmark>;
+}
+
+/// A bookmarked message.
+#[derive(Debug, Clone)]
+pub struct Bookmark {
+    pub room: RoomIdentifier,
+    pub note: Option<String>,
+    pub created: Time,
+    pub msg: Message,
+}
+
+#[derive(Debug)]
+pub struct RoomStats {
+    pub room: RoomIdentifier,
+    pub msgs: usize,
+    pub unseen_msgs: usize,
+    pub first_joined: Time,
+    pub last_joined: Time,
+    /// Time of the oldest and newest message in the vault.
+    pub covered: Option<(Time, Time)>,
+    pub gaps: usize,
+    /// Rough estimate of the space the room's messages take up, in bytes.
+    pub approx_size: u64,
+}
+
+/// Problems found in a room by [`EuphVault::fsck`].
+#[derive(Debug)]
+pub struct FsckReport {
+    pub room: RoomIdentifier,
+    /// The room has messages or spans but no entry in `euph_rooms`.
+    pub missing_room: bool,
+    /// Spans whose start lies after their end.
+    pub malformed_spans: usize,
+    /// Spans overlapping or touching their predecessor.
+    pub overlapping_spans: usize,
+    /// Missing parents of messages.
+    ///
+    /// These are usually messages deleted by moderators, which the server no
+    /// longer returns. They are reported for information only.
+    pub orphaned_parents: usize,
+    /// Missing parents of messages that lie inside a span. Like
+    /// [`Self::orphaned_parents`], these are reported for information only.
+    pub missing_parents_in_spans: usize,
+    /// Messages not covered by any span.
+    ///
+    /// These lie in gaps of the room history and are downloaded again once the
+    /// gaps are filled. They are reported for information only.
+    pub msgs_outside_spans: usize,
+}
+
+impl FsckReport {
+    pub fn is_ok(&self) -> bool {
+        !self.missing_room && self.malformed_spans == 0 && self.overlapping_spans == 0
+    }
+}
+
+/// How to combine the seen status of messages when merging vaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeSeen {
+    /// Keep the seen status of messages already in this vault.
+    Ours,
+    /// Use the seen status from the other vault.
+    Theirs,
+    /// Messages are seen if they are seen in either vault.
+    Any,
+    /// Messages are seen only if they are seen in both vaults.
+    All,
 }
 
 impl Action for GetCookies {
@@ -218,210 +319,1067 @@ impl Action for GetTotalUnseenMsgsCount {
     type Error = rusqlite::Error;
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
-        conn.prepare(
+        conn.prepare(&format!(
             "
-                SELECT COALESCE(SUM(amount), 0)
-                FROM euph_unseen_counts
-                ",
-        )?
-        .query_row([], |row| row.get(0))
+            SELECT COALESCE(SUM(amount), 0) - (
+                SELECT COUNT(*)
+                FROM euph_msgs
+                WHERE NOT seen
+                AND deleted IS NOT NULL
+                AND {HIDDEN}
+            )
+            FROM euph_unseen_counts
+            "
+        ))?
+        .query_row([self.hide_deleted], |row| row.get(0))
     }
 }
 
-///////////////////
-// EuphRoomVault //
-///////////////////
+impl Action for Merge {
+    type Output = usize;
+    type Error = rusqlite::Error;
 
-#[derive(Debug, Clone)]
-pub struct EuphRoomVault {
-    vault: EuphVault,
-    room: RoomIdentifier,
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        conn.execute("ATTACH DATABASE ? AS other", [self.path.to_string_lossy()])?;
+        let result = merge_attached(conn, self.seen);
+        conn.execute_batch("DETACH DATABASE other")?;
+        result
+    }
 }
 
-impl EuphRoomVault {
-    pub fn vault(&self) -> &EuphVault {
-        &self.vault
+fn merge_attached(conn: &mut Connection, seen: MergeSeen) -> rusqlite::Result<usize> {
+    let our_version = conn.query_row("PRAGMA main.user_version", [], |row| row.get::<_, i64>(0))?;
+    let their_version =
+        conn.query_row("PRAGMA other.user_version", [], |row| row.get::<_, i64>(0))?;
+    if our_version != their_version {
+        return Err(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_MISMATCH),
+            Some(format!(
+                "other vault has version {their_version} instead of {our_version}, \
+                 open it with this version of cove first to migrate it"
+            )),
+        ));
     }
 
-    pub fn room(&self) -> &RoomIdentifier {
-        &self.room
-    }
-}
+    let tx = conn.transaction()?;
 
-macro_rules! euph_room_vault_actions {
-    ( $(
-        $struct:ident : $fn:ident ( $( $arg:ident : $arg_ty:ty ),* ) -> $res:ty ;
-    )* ) => {
-        $(
-            struct $struct {
-                room: RoomIdentifier,
-                $( $arg: $arg_ty, )*
-            }
-        )*
+    // Like when moving rooms, the history of a room is only hidden from being
+    // downloaded again if it was pruned in both vaults.
+    tx.execute(
+        "
+        INSERT INTO main.euph_rooms (domain, room, first_joined, last_joined, pruned_before)
+        SELECT domain, room, first_joined, last_joined, pruned_before
+        FROM other.euph_rooms
+        WHERE true
+        ON CONFLICT (domain, room) DO UPDATE
+        SET
+            first_joined = min(first_joined, excluded.first_joined),
+            last_joined = max(last_joined, excluded.last_joined),
+            pruned_before = CASE
+                WHEN pruned_before IS NULL OR excluded.pruned_before IS NULL THEN NULL
+                ELSE min(pruned_before, excluded.pruned_before)
+            END
+        ",
+        [],
+    )?;
 
-        impl EuphRoomVault {
-            $(
-                pub async fn $fn(&self, $( $arg: $arg_ty, )* ) -> Result<$res, vault::tokio::Error<rusqlite::Error>> {
-                    self.vault.vault.tokio_vault.execute($struct {
-                        room: self.room.clone(),
-                        $( $arg, )*
-                    }).await
-                }
-            )*
-        }
+    // Rows inserted from now on get rowids greater than this, which is used to
+    // find the messages that need to be added to the full-text index.
+    let max_rowid = tx.query_row(
+        "SELECT COALESCE(MAX(rowid), 0) FROM main.euph_msgs",
+        [],
+        |row| row.get::<_, i64>(0),
+    )?;
+
+    let on_conflict = match seen {
+        MergeSeen::Ours => "NOTHING",
+        MergeSeen::Theirs => "UPDATE SET seen = excluded.seen",
+        MergeSeen::Any => "UPDATE SET seen = seen OR excluded.seen",
+        MergeSeen::All => "UPDATE SET seen = seen AND excluded.seen",
     };
-}
+    tx.execute(
+        &format!(
+            "
+            INSERT INTO main.euph_msgs (
+                domain, room, seen,
+                id, parent, previous_edit_id, time, content, encryption_key_id, edited, deleted, truncated,
+                user_id, name, server_id, server_era, session_id, is_staff, is_manager, client_address, real_client_address
+            )
+            SELECT
+                domain, room, seen,
+                id, parent, previous_edit_id, time, content, encryption_key_id, edited, deleted, truncated,
+                user_id, name, server_id, server_era, session_id, is_staff, is_manager, client_address, real_client_address
+            FROM other.euph_msgs
+            WHERE NOT EXISTS(
+                SELECT *
+                FROM main.euph_rooms
+                WHERE main.euph_rooms.domain = other.euph_msgs.domain
+                AND main.euph_rooms.room = other.euph_msgs.room
+                AND other.euph_msgs.id < main.euph_rooms.pruned_before
+            )
+            ON CONFLICT (domain, room, id) DO {on_conflict}
+            "
+        ),
+        [],
+    )?;
 
-euph_room_vault_actions! {
-    // Room
-    Join : join(time: Time) -> ();
-    Delete : delete() -> ();
+    let new_msgs = tx.execute(
+        "
+        INSERT INTO euph_msgs_fts (rowid, content, name)
+        SELECT rowid, content, name
+        FROM main.euph_msgs
+        WHERE rowid > ?
+        ",
+        [max_rowid],
+    )?;
 
-    // Message
-    AddMsg : add_msg(msg: Box<Message>, prev_msg_id: Option<MessageId>, own_user_id: Option<UserId>) -> ();
-    AddMsgs : add_msgs(msgs: Vec<Message>, next_msg_id: Option<MessageId>, own_user_id: Option<UserId>) -> ();
-    GetLastSpan : last_span() -> Option<(Option<MessageId>, Option<MessageId>)>;
-    GetPath : path(id: MessageId) -> Path<MessageId>;
-    GetMsg : msg(id: MessageId) -> Option<SmallMessage>;
-    GetFullMsg : full_msg(id: MessageId) -> Option<Message>;
-    GetTree : tree(root_id: MessageId) -> Tree<SmallMessage>;
-    GetFirstRootId : first_root_id() -> Option<MessageId>;
-    GetLastRootId : last_root_id() -> Option<MessageId>;
-    GetPrevRootId : prev_root_id(root_id: MessageId) -> Option<MessageId>;
-    GetNextRootId : next_root_id(root_id: MessageId) -> Option<MessageId>;
-    GetOldestMsgId : oldest_msg_id() -> Option<MessageId>;
-    GetNewestMsgId : newest_msg_id() -> Option<MessageId>;
-    GetOlderMsgId : older_msg_id(id: MessageId) -> Option<MessageId>;
-    GetNewerMsgId : newer_msg_id(id: MessageId) -> Option<MessageId>;
-    GetOldestUnseenMsgId : oldest_unseen_msg_id() -> Option<MessageId>;
-    GetNewestUnseenMsgId : newest_unseen_msg_id() -> Option<MessageId>;
-    GetOlderUnseenMsgId : older_unseen_msg_id(id: MessageId) -> Option<MessageId>;
-    GetNewerUnseenMsgId : newer_unseen_msg_id(id: MessageId) -> Option<MessageId>;
-    GetUnseenMsgsCount : unseen_msgs_count() -> usize;
-    SetSeen : set_seen(id: MessageId, seen: bool) -> ();
-    SetOlderSeen : set_older_seen(id: MessageId, seen: bool) -> ();
-    GetChunkAfter : chunk_after(id: Option<MessageId>, amount: usize) -> Vec<Message>;
+    tx.execute(
+        "
+        INSERT INTO main.euph_bookmarks (domain, room, id, note, created)
+        SELECT domain, room, id, note, created
+        FROM other.euph_bookmarks
+        WHERE EXISTS(
+            SELECT *
+            FROM main.euph_msgs
+            WHERE main.euph_msgs.domain = other.euph_bookmarks.domain
+            AND main.euph_msgs.room = other.euph_bookmarks.room
+            AND main.euph_msgs.id = other.euph_bookmarks.id
+        )
+        ON CONFLICT (domain, room, id) DO NOTHING
+        ",
+        [],
+    )?;
+
+    let mut spans = HashMap::<RoomIdentifier, Vec<_>>::new();
+    {
+        let mut stmt = tx.prepare(
+            "
+            SELECT domain, room, start, end
+            FROM other.euph_spans
+            ",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let room = RoomIdentifier {
+                domain: row.get(0)?,
+                name: row.get(1)?,
+            };
+            let start = row.get::<_, Option<WSnowflake>>(2)?.map(|s| MessageId(s.0));
+            let end = row.get::<_, Option<WSnowflake>>(3)?.map(|s| MessageId(s.0));
+            Ok((room, (start, end)))
+        })?;
+        for row in rows {
+            let (room, span) = row?;
+            spans.entry(room).or_default().push(span);
+        }
+    }
+    for (room, spans) in spans {
+        add_spans(&tx, &room, spans)?;
+    }
+
+    tx.commit()?;
+    Ok(new_msgs)
 }
 
-impl Action for Join {
-    type Output = ();
+impl Action for GetBookmarks {
+    type Output = Vec<Bookmark>;
     type Error = rusqlite::Error;
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
-        conn.execute(
+        conn.prepare(
             "
-            INSERT INTO euph_rooms (domain, room, first_joined, last_joined)
-            VALUES (:domain, :room, :time, :time)
-            ON CONFLICT (domain, room) DO UPDATE
-            SET last_joined = :time
-            ",
-            named_params! {
-                ":domain": self.room.domain,
-                ":room": self.room.name,
-                ":time": WTime(self.time),
-            },
-        )?;
-        Ok(())
+            SELECT
+                id, parent, previous_edit_id, time, content, encryption_key_id, edited, deleted, truncated,
+                user_id, name, server_id, server_era, session_id, is_staff, is_manager, client_address, real_client_address,
+                domain, room, note, created
+            FROM euph_bookmarks
+            JOIN euph_msgs USING (domain, room, id)
+            ORDER BY domain ASC, room ASC, id ASC
+            "
+        )?
+        .query_map([], |row| {
+            Ok(Bookmark {
+                room: RoomIdentifier {
+                    domain: row.get(18)?,
+                    name: row.get(19)?,
+                },
+                note: row.get(20)?,
+                created: row.get::<_, WTime>(21)?.0,
+                msg: row_to_msg(row)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()
     }
 }
 
-impl Action for Delete {
-    type Output = ();
+impl Action for MoveRooms {
+    type Output = usize;
     type Error = rusqlite::Error;
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
-        conn.execute(
-            "
-            DELETE FROM euph_rooms
-            WHERE domain = ?
-            AND room = ?
-            ",
-            [&self.room.domain, &self.room.name],
-        )?;
-        Ok(())
+        // Moving rooms onto themselves would delete them.
+        if self.from == self.to {
+            return Ok(0);
+        }
+
+        let tx = conn.transaction()?;
+
+        let names = tx
+            .prepare(
+                "
+                SELECT room
+                FROM euph_rooms
+                WHERE domain = ?
+                AND (?2 IS NULL OR room = ?2)
+                ",
+            )?
+            .query_map(params![self.from, self.room], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        for name in &names {
+            let from = RoomIdentifier::new(self.from.clone(), name.clone());
+            let to = RoomIdentifier::new(self.to.clone(), name.clone());
+            move_room(&tx, &from, &to)?;
+        }
+
+        // Cookies belong to the whole domain, not to individual rooms. If the
+        // new domain already has cookies, they are kept.
+        if self.room.is_none() {
+            tx.execute(
+                "
+                UPDATE euph_cookies
+                SET domain = :to
+                WHERE domain = :from
+                AND NOT EXISTS (
+                    SELECT 1
+                    FROM euph_cookies
+                    WHERE domain = :to
+                )
+                ",
+                named_params! { ":from": self.from, ":to": self.to },
+            )?;
+            tx.execute("DELETE FROM euph_cookies WHERE domain = ?", [&self.from])?;
+        }
+
+        tx.commit()?;
+        Ok(names.len())
     }
 }
 
-fn insert_msgs(
+/// Move a room's messages and spans to another room, merging them with the
+/// other room's contents if it already exists.
+///
+/// Messages present in both rooms keep the other room's version and are seen
+/// if they are seen in either room.
+fn move_room(
     tx: &Transaction<'_>,
-    room: &RoomIdentifier,
-    own_user_id: &Option<UserId>,
-    msgs: Vec<Message>,
+    from: &RoomIdentifier,
+    to: &RoomIdentifier,
 ) -> rusqlite::Result<()> {
-    let mut insert_msg = tx.prepare(
+    // If only one of the rooms was pruned, the history of the other room is
+    // still complete and must not be hidden from being downloaded again.
+    tx.execute(
+        "
+        INSERT INTO euph_rooms (domain, room, first_joined, last_joined, pruned_before)
+        SELECT :to_domain, :to_room, first_joined, last_joined, pruned_before
+        FROM euph_rooms
+        WHERE domain = :from_domain
+        AND room = :from_room
+        ON CONFLICT (domain, room) DO UPDATE
+        SET
+            first_joined = min(first_joined, excluded.first_joined),
+            last_joined = max(last_joined, excluded.last_joined),
+            pruned_before = CASE
+                WHEN pruned_before IS NULL OR excluded.pruned_before IS NULL THEN NULL
+                ELSE min(pruned_before, excluded.pruned_before)
+            END
+        ",
+        named_params! {
+            ":from_domain": from.domain,
+            ":from_room": from.name,
+            ":to_domain": to.domain,
+            ":to_room": to.name,
+        },
+    )?;
+
+    // Rows inserted from now on get rowids greater than this, which is used to
+    // find the messages that need to be added to the full-text index.
+    let max_rowid = tx.query_row("SELECT COALESCE(MAX(rowid), 0) FROM euph_msgs", [], |row| {
+        row.get::<_, i64>(0)
+    })?;
+
+    tx.execute(
         "
         INSERT INTO euph_msgs (
-            domain, room,
+            domain, room, seen,
             id, parent, previous_edit_id, time, content, encryption_key_id, edited, deleted, truncated,
-            user_id, name, server_id, server_era, session_id, is_staff, is_manager, client_address, real_client_address,
-            seen
-        )
-        VALUES (
-            :domain, :room,
-            :id, :parent, :previous_edit_id, :time, :content, :encryption_key_id, :edited, :deleted, :truncated,
-            :user_id, :name, :server_id, :server_era, :session_id, :is_staff, :is_manager, :client_address, :real_client_address,
-            (:user_id == :own_user_id OR EXISTS(
-                SELECT 1
-                FROM euph_rooms
-                WHERE domain = :domain
-                AND room = :room
-                AND :time < first_joined
-            ))
+            user_id, name, server_id, server_era, session_id, is_staff, is_manager, client_address, real_client_address
         )
+        SELECT
+            :to_domain, :to_room, seen,
+            id, parent, previous_edit_id, time, content, encryption_key_id, edited, deleted, truncated,
+            user_id, name, server_id, server_era, session_id, is_staff, is_manager, client_address, real_client_address
+        FROM euph_msgs
+        WHERE domain = :from_domain
+        AND room = :from_room
         ON CONFLICT (domain, room, id) DO UPDATE
-        SET
-            domain = :domain,
-            room = :room,
-            id = :id,
-            parent = :parent,
-            previous_edit_id = :previous_edit_id,
-            time = :time,
-            content = :content,
-            encryption_key_id = :encryption_key_id,
-            edited = :edited,
-            deleted = :deleted,
-            truncated = :truncated,
+        SET seen = seen OR excluded.seen
+        ",
+        named_params! {
+            ":from_domain": from.domain,
+            ":from_room": from.name,
+            ":to_domain": to.domain,
+            ":to_room": to.name,
+        },
+    )?;
 
-            user_id = :user_id,
-            name = :name,
-            server_id = :server_id,
-            server_era = :server_era,
-            session_id = :session_id,
-            is_staff = :is_staff,
-            is_manager = :is_manager,
-            client_address = :client_address,
-            real_client_address = :real_client_address
+    tx.execute(
         "
+        INSERT INTO euph_msgs_fts (rowid, content, name)
+        SELECT rowid, content, name
+        FROM euph_msgs
+        WHERE rowid > ?
+        ",
+        [max_rowid],
     )?;
 
-    let own_user_id = own_user_id.as_ref().map(|u| &u.0);
-    for msg in msgs {
-        insert_msg.execute(named_params! {
-            ":domain": room.domain,
-            ":room": room.name,
-            ":id": WSnowflake(msg.id.0),
-            ":parent": msg.parent.map(|id| WSnowflake(id.0)),
-            ":previous_edit_id": msg.previous_edit_id.map(WSnowflake),
-            ":time": WTime(msg.time),
-            ":content": msg.content,
-            ":encryption_key_id": msg.encryption_key_id,
-            ":edited": msg.edited.map(WTime),
-            ":deleted": msg.deleted.map(WTime),
-            ":truncated": msg.truncated,
-            ":user_id": msg.sender.id.0,
-            ":name": msg.sender.name,
-            ":server_id": msg.sender.server_id,
-            ":server_era": msg.sender.server_era,
-            ":session_id": msg.sender.session_id.0,
-            ":is_staff": msg.sender.is_staff,
-            ":is_manager": msg.sender.is_manager,
+    let spans = tx
+        .prepare(
+            "
+            SELECT start, end
+            FROM euph_spans
+            WHERE domain = ?
+            AND room = ?
+            ",
+        )?
+        .query_map([&from.domain, &from.name], |row| {
+            let start = row.get::<_, Option<WSnowflake>>(0)?.map(|s| MessageId(s.0));
+            let end = row.get::<_, Option<WSnowflake>>(1)?.map(|s| MessageId(s.0));
+            Ok((start, end))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    add_spans(tx, to, spans)?;
+
+    tx.execute(
+        "
+        INSERT INTO euph_bookmarks (domain, room, id, note, created)
+        SELECT :to_domain, :to_room, id, note, created
+        FROM euph_bookmarks
+        WHERE domain = :from_domain
+        AND room = :from_room
+        ON CONFLICT (domain, room, id) DO NOTHING
+        ",
+        named_params! {
+            ":from_domain": from.domain,
+            ":from_room": from.name,
+            ":to_domain": to.domain,
+            ":to_room": to.name,
+        },
+    )?;
+
+    tx.execute(
+        "
+        INSERT INTO euph_msgs_fts (euph_msgs_fts, rowid, content, name)
+        SELECT 'delete', rowid, content, name
+        FROM euph_msgs
+        WHERE domain = ?
+        AND room = ?
+        ",
+        [&from.domain, &from.name],
+    )?;
+
+    tx.execute(
+        "
+        DELETE FROM euph_rooms
+        WHERE domain = ?
+        AND room = ?
+        ",
+        [&from.domain, &from.name],
+    )?;
+
+    Ok(())
+}
+
+impl Action for GetRoomStats {
+    type Output = Vec<RoomStats>;
+    type Error = rusqlite::Error;
+
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        // The size estimate counts the bytes of all text columns plus a fixed
+        // amount for the integer columns and row overhead.
+        let mut stats = conn
+            .prepare(
+                "
+                SELECT
+                    domain, room, first_joined, last_joined,
+                    COUNT(id),
+                    COALESCE(SUM(NOT seen), 0),
+                    MIN(time),
+                    MAX(time),
+                    COALESCE(SUM(
+                        64
+                        + LENGTH(CAST(content AS BLOB))
+                        + LENGTH(CAST(COALESCE(name, '') AS BLOB))
+                        + LENGTH(CAST(COALESCE(encryption_key_id, '') AS BLOB))
+                        + LENGTH(CAST(user_id AS BLOB))
+                        + LENGTH(CAST(server_id AS BLOB))
+                        + LENGTH(CAST(server_era AS BLOB))
+                        + LENGTH(CAST(session_id AS BLOB))
+                        + LENGTH(CAST(COALESCE(client_address, '') AS BLOB))
+                        + LENGTH(CAST(COALESCE(real_client_address, '') AS BLOB))
+                    ), 0)
+                FROM euph_rooms
+                LEFT JOIN euph_msgs USING (domain, room)
+                GROUP BY domain, room
+                ORDER BY domain, room
+                ",
+            )?
+            .query_map([], |row| {
+                let oldest = row.get::<_, Option<WTime>>(6)?;
+                let newest = row.get::<_, Option<WTime>>(7)?;
+                Ok(RoomStats {
+                    room: RoomIdentifier {
+                        domain: row.get(0)?,
+                        name: row.get(1)?,
+                    },
+                    first_joined: row.get::<_, WTime>(2)?.0,
+                    last_joined: row.get::<_, WTime>(3)?.0,
+                    msgs: row.get(4)?,
+                    unseen_msgs: row.get(5)?,
+                    covered: oldest.zip(newest).map(|(o, n)| (o.0, n.0)),
+                    gaps: 0,
+                    approx_size: row.get(8)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        for stats in &mut stats {
+            let gaps = GetGaps {
+                room: stats.room.clone(),
+            }
+            .run(conn)?;
+            stats.gaps = gaps.len();
+        }
+
+        Ok(stats)
+    }
+}
+
+impl Action for Fsck {
+    type Output = Vec<FsckReport>;
+    type Error = rusqlite::Error;
+
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        let tx = conn.transaction()?;
+
+        let missing_rooms = tx
+            .prepare(
+                "
+                SELECT domain, room FROM euph_msgs
+                UNION
+                SELECT domain, room FROM euph_spans
+                EXCEPT
+                SELECT domain, room FROM euph_rooms
+                ",
+            )?
+            .query_map([], |row| {
+                Ok(RoomIdentifier {
+                    domain: row.get(0)?,
+                    name: row.get(1)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut rooms = tx
+            .prepare("SELECT domain, room FROM euph_rooms")?
+            .query_map([], |row| {
+                Ok(RoomIdentifier {
+                    domain: row.get(0)?,
+                    name: row.get(1)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rooms.extend(missing_rooms.iter().cloned());
+        rooms.sort_unstable();
+
+        let mut reports = vec![];
+        for room in rooms {
+            let missing_room = missing_rooms.contains(&room);
+            let report = fsck_room(&tx, room, missing_room, self.fix)?;
+            reports.push(report);
+        }
+
+        tx.commit()?;
+        Ok(reports)
+    }
+}
+
+type Span = (Option<MessageId>, Option<MessageId>);
+
+fn span_contains(span: &Span, id: MessageId) -> bool {
+    span.0.is_none_or(|start| start <= id) && span.1.is_none_or(|end| id <= end)
+}
+
+fn delete_span(tx: &Transaction<'_>, room: &RoomIdentifier, span: &Span) -> rusqlite::Result<()> {
+    tx.execute(
+        "
+        DELETE FROM euph_spans
+        WHERE domain = ?
+        AND room = ?
+        AND start IS ?
+        AND end IS ?
+        ",
+        params![
+            room.domain,
+            room.name,
+            span.0.map(|id| WSnowflake(id.0)),
+            span.1.map(|id| WSnowflake(id.0)),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Amount of messages not covered by any span.
+fn msgs_outside_spans(tx: &Transaction<'_>, room: &RoomIdentifier) -> rusqlite::Result<usize> {
+    tx.query_row(
+        "
+        SELECT COUNT(*)
+        FROM euph_msgs
+        WHERE domain = :domain
+        AND room = :room
+        AND NOT EXISTS(
+            SELECT *
+            FROM euph_spans
+            WHERE domain = :domain
+            AND room = :room
+            AND (start IS NULL OR start <= euph_msgs.id)
+            AND (end IS NULL OR euph_msgs.id <= end)
+        )
+        ",
+        named_params! { ":domain": room.domain, ":room": room.name },
+        |row| row.get(0),
+    )
+}
+
+fn fsck_room(
+    tx: &Transaction<'_>,
+    room: RoomIdentifier,
+    missing_room: bool,
+    fix: bool,
+) -> rusqlite::Result<FsckReport> {
+    let mut spans = tx
+        .prepare(
+            "
+            SELECT start, end
+            FROM euph_spans
+            WHERE domain = ?
+            AND room = ?
+            ",
+        )?
+        .query_map([&room.domain, &room.name], |row| {
+            let start = row.get::<_, Option<WSnowflake>>(0)?.map(|s| MessageId(s.0));
+            let end = row.get::<_, Option<WSnowflake>>(1)?.map(|s| MessageId(s.0));
+            Ok((start, end))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    spans.sort_unstable();
+
+    let malformed_spans = spans
+        .iter()
+        .filter(|(start, end)| matches!((start, end), (Some(start), Some(end)) if start > end))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut overlapping_spans = 0;
+    let mut prev_end = None;
+    for (i, (start, end)) in spans.iter().enumerate() {
+        // A span ending in the present overlaps every later span
+        if i > 0 && (prev_end.is_none() || *start <= prev_end) {
+            overlapping_spans += 1;
+        }
+        prev_end = *end;
+    }
+
+    let orphaned_parents = tx
+        .prepare(
+            "
+            SELECT DISTINCT parent
+            FROM euph_msgs
+            WHERE domain = :domain
+            AND room = :room
+            AND parent IS NOT NULL
+            AND NOT EXISTS(
+                SELECT *
+                FROM euph_msgs AS parents
+                WHERE parents.domain = :domain
+                AND parents.room = :room
+                AND parents.id = euph_msgs.parent
+            )
+            ",
+        )?
+        .query_map(
+            named_params! { ":domain": room.domain, ":room": room.name },
+            |row| row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0)),
+        )?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let missing_parents_in_spans = orphaned_parents
+        .iter()
+        .filter(|id| spans.iter().any(|span| span_contains(span, **id)))
+        .count();
+
+    let msgs_outside_spans = msgs_outside_spans(tx, &room)?;
+
+    let report = FsckReport {
+        room,
+        missing_room,
+        malformed_spans: malformed_spans.len(),
+        overlapping_spans,
+        orphaned_parents: orphaned_parents.len(),
+        missing_parents_in_spans,
+        msgs_outside_spans,
+    };
+
+    if !fix || report.is_ok() {
+        return Ok(report);
+    }
+    let room = &report.room;
+
+    if missing_room {
+        tx.execute(
+            "
+            INSERT INTO euph_rooms (domain, room, first_joined, last_joined)
+            SELECT :domain, :room, COALESCE(MIN(time), :now), COALESCE(MAX(time), :now)
+            FROM euph_msgs
+            WHERE domain = :domain
+            AND room = :room
+            ",
+            named_params! {
+                ":domain": room.domain,
+                ":room": room.name,
+                ":now": WTime(Time::now()),
+            },
+        )?;
+    }
+
+    // Spans only serve to avoid downloading messages again, so removing
+    // malformed spans is always safe.
+    for span in &malformed_spans {
+        delete_span(tx, room, span)?;
+    }
+
+    // Adding spans merges all overlapping spans. Messages outside of spans are
+    // not covered by new spans since there may be unknown messages between
+    // them.
+    add_spans(tx, room, vec![])?;
+
+    Ok(report)
+}
+
+///////////////////
+// EuphRoomVault //
+///////////////////
+
+/// Conditions that messages returned by
+/// [`EuphRoomVault::filtered_chunk_after`] must fulfill.
+#[derive(Debug, Clone, Default)]
+pub struct MsgFilter {
+    /// Exact nick of the sender.
+    pub nick: Option<String>,
+    pub user_id: Option<UserId>,
+    /// Inclusive lower bound of the message time.
+    pub since: Option<Time>,
+    /// Exclusive upper bound of the message time.
+    pub until: Option<Time>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EuphRoomVault {
+    vault: EuphVault,
+    room: RoomIdentifier,
+    hide_deleted: bool,
+}
+
+impl EuphRoomVault {
+    pub fn vault(&self) -> &EuphVault {
+        &self.vault
+    }
+
+    pub fn room(&self) -> &RoomIdentifier {
+        &self.room
+    }
+
+    /// Hide deleted messages without any replies from the [`MsgStore`]
+    /// implementation. Other deleted messages are still visible.
+    pub fn with_hide_deleted(mut self, hide_deleted: bool) -> Self {
+        self.hide_deleted = hide_deleted;
+        self
+    }
+}
+
+/// Condition matching messages in `euph_msgs` that are hidden when deleted
+/// messages should be hidden.
+///
+/// Only deleted messages without any replies are hidden so that trees never
+/// lose their structure. The condition contains a single positional parameter
+/// that determines whether messages should be hidden at all.
+macro_rules! hidden_sql {
+    () => {
+        "
+        (? AND euph_msgs.deleted IS NOT NULL AND NOT EXISTS(
+            SELECT *
+            FROM euph_msgs AS children
+            WHERE children.domain = euph_msgs.domain
+            AND children.room = euph_msgs.room
+            AND children.parent = euph_msgs.id
+        ))
+        "
+    };
+}
+
+/// See [`hidden_sql`].
+const HIDDEN: &str = hidden_sql!();
+
+/// Like [`HIDDEN`], but matching trees in `euph_trees` whose root is hidden.
+const ROOT_HIDDEN: &str = concat!(
+    "
+    EXISTS(
+        SELECT *
+        FROM euph_msgs
+        WHERE euph_msgs.domain = euph_trees.domain
+        AND euph_msgs.room = euph_trees.room
+        AND euph_msgs.id = euph_trees.id
+        AND ",
+    hidden_sql!(),
+    "
+    )
+    "
+);
+
+macro_rules! euph_room_vault_actions {
+    ( $(
+        $( #[$attr:meta] )*
+        $struct:ident : $fn:ident ( $( $arg:ident : $arg_ty:ty ),* ) -> $res:ty ;
+    )* ) => {
+        $(
+            $( #[$attr] )*
+            struct $struct {
+                room: RoomIdentifier,
+                $( $arg: $arg_ty, )*
+            }
+        )*
+
+        impl EuphRoomVault {
+            $(
+                $( #[$attr] )*
+                pub async fn $fn(&self, $( $arg: $arg_ty, )* ) -> Result<$res, vault::tokio::Error<rusqlite::Error>> {
+                    self.vault.vault.tokio_vault.execute($struct {
+                        room: self.room.clone(),
+                        $( $arg, )*
+                    }).await
+                }
+            )*
+        }
+    };
+}
+
+/// Like [`euph_room_vault_actions`], but the actions additionally receive the
+/// vault's `hide_deleted` setting (see [`EuphRoomVault::with_hide_deleted`]).
+macro_rules! euph_room_vault_hiding_actions {
+    ( $(
+        $( #[$attr:meta] )*
+        $struct:ident : $fn:ident ( $( $arg:ident : $arg_ty:ty ),* ) -> $res:ty ;
+    )* ) => {
+        $(
+            $( #[$attr] )*
+            struct $struct {
+                room: RoomIdentifier,
+                hide_deleted: bool,
+                $( $arg: $arg_ty, )*
+            }
+        )*
+
+        impl EuphRoomVault {
+            $(
+                $( #[$attr] )*
+                pub async fn $fn(&self, $( $arg: $arg_ty, )* ) -> Result<$res, vault::tokio::Error<rusqlite::Error>> {
+                    self.vault.vault.tokio_vault.execute($struct {
+                        room: self.room.clone(),
+                        hide_deleted: self.hide_deleted,
+                        $( $arg, )*
+                    }).await
+                }
+            )*
+        }
+    };
+}
+
+euph_room_vault_actions! {
+    // Room
+    Join : join(time: Time) -> ();
+    Delete : delete() -> ();
+
+    // Message
+    AddMsg : add_msg(msg: Box<Message>, prev_msg_id: Option<MessageId>, own_user_id: Option<UserId>) -> ();
+    AddMsgs : add_msgs(msgs: Vec<Message>, next_msg_id: Option<MessageId>, own_user_id: Option<UserId>) -> ();
+    /// Gaps in the room history as `(after, before)` pairs, oldest gap first.
+    ///
+    /// An `after` of `None` means the gap extends to the beginning of the
+    /// room's history. Rooms without any spans have no gaps.
+    GetGaps : gaps() -> Vec<(Option<MessageId>, MessageId)>;
+    GetPath : path(id: MessageId) -> Path<MessageId>;
+    EditMsg : edit_msg(edit_id: Snowflake, msg: Box<Message>) -> ();
+    MarkDeleted : mark_deleted(ids: Vec<MessageId>, time: Time) -> ();
+    UpdateMsg : update_msg(msg: Box<Message>) -> ();
+    /// The truncated messages among `prioritized` in their original order,
+    /// followed by up to `amount` of the newest truncated messages.
+    GetTruncatedMsgIds : truncated_msg_ids(prioritized: Vec<MessageId>, amount: usize) -> Vec<MessageId>;
+    GetMsg : msg(id: MessageId) -> Option<SmallMessage>;
+    GetFullMsg : full_msg(id: MessageId) -> Option<Message>;
+    SetSeen : set_seen(id: MessageId, seen: bool) -> ();
+    SetOlderSeen : set_older_seen(id: MessageId, seen: bool) -> ();
+    GetChunkAfter : chunk_after(id: Option<MessageId>, amount: usize) -> Vec<Message>;
+    GetChunkBefore : chunk_before(id: Option<MessageId>, amount: usize) -> Vec<Message>;
+    ImportMsgs : import_msgs(msgs: Vec<Message>, time: Time, complete_history: bool) -> usize;
+    Prune : prune(older_than: Option<Time>, keep_newest: Option<usize>) -> usize;
+    GetPrunedBefore : pruned_before() -> Option<MessageId>;
+    GetFilteredChunkAfter : filtered_chunk_after(id: Option<MessageId>, amount: usize, filter: MsgFilter) -> Vec<Message>;
+    GetActiveRootIds : active_root_ids(since: Option<Time>, until: Option<Time>) -> Vec<MessageId>;
+    GetTreeMsgs : tree_msgs(root_id: MessageId) -> Vec<Message>;
+
+    // Bookmark
+    AddBookmark : add_bookmark(id: MessageId, note: Option<String>, time: Time) -> ();
+    RemoveBookmark : remove_bookmark(id: MessageId) -> bool;
+}
+
+euph_room_vault_hiding_actions! {
+    GetTree : tree(root_id: MessageId) -> Tree<SmallMessage>;
+    GetFirstRootId : first_root_id() -> Option<MessageId>;
+    GetLastRootId : last_root_id() -> Option<MessageId>;
+    GetPrevRootId : prev_root_id(root_id: MessageId) -> Option<MessageId>;
+    GetNextRootId : next_root_id(root_id: MessageId) -> Option<MessageId>;
+    GetOldestMsgId : oldest_msg_id() -> Option<MessageId>;
+    GetNewestMsgId : newest_msg_id() -> Option<MessageId>;
+    GetOlderMsgId : older_msg_id(id: MessageId) -> Option<MessageId>;
+    GetNewerMsgId : newer_msg_id(id: MessageId) -> Option<MessageId>;
+    GetOldestUnseenMsgId : oldest_unseen_msg_id() -> Option<MessageId>;
+    GetNewestUnseenMsgId : newest_unseen_msg_id() -> Option<MessageId>;
+    GetOlderUnseenMsgId : older_unseen_msg_id(id: MessageId) -> Option<MessageId>;
+    GetNewerUnseenMsgId : newer_unseen_msg_id(id: MessageId) -> Option<MessageId>;
+    GetUnseenMsgsCount : unseen_msgs_count() -> usize;
+    SearchMsgs : search_msgs(query: String, amount: usize) -> Vec<SmallMessage>;
+}
+
+impl Action for Join {
+    type Output = ();
+    type Error = rusqlite::Error;
+
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        conn.execute(
+            "
+            INSERT INTO euph_rooms (domain, room, first_joined, last_joined)
+            VALUES (:domain, :room, :time, :time)
+            ON CONFLICT (domain, room) DO UPDATE
+            SET last_joined = :time
+            ",
+            named_params! {
+                ":domain": self.room.domain,
+                ":room": self.room.name,
+                ":time": WTime(self.time),
+            },
+        )?;
+        Ok(())
+    }
+}
+
+impl Action for Delete {
+    type Output = ();
+    type Error = rusqlite::Error;
+
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "
+            INSERT INTO euph_msgs_fts (euph_msgs_fts, rowid, content, name)
+            SELECT 'delete', rowid, content, name
+            FROM euph_msgs
+            WHERE domain = ?
+            AND room = ?
+            ",
+            [&self.room.domain, &self.room.name],
+        )?;
+
+        tx.execute(
+            "
+            DELETE FROM euph_rooms
+            WHERE domain = ?
+            AND room = ?
+            ",
+            [&self.room.domain, &self.room.name],
+        )?;
+
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// Remove a message from the full-text index.
+///
+/// Must be called before the message's content or nick change and before the
+/// message is deleted. Does nothing if the message doesn't exist.
+fn fts_remove_msg(
+    tx: &Transaction<'_>,
+    room: &RoomIdentifier,
+    id: MessageId,
+) -> rusqlite::Result<()> {
+    tx.prepare_cached(
+        "
+        INSERT INTO euph_msgs_fts (euph_msgs_fts, rowid, content, name)
+        SELECT 'delete', rowid, content, name
+        FROM euph_msgs
+        WHERE domain = ?
+        AND room = ?
+        AND id = ?
+        ",
+    )?
+    .execute(params![room.domain, room.name, WSnowflake(id.0)])?;
+    Ok(())
+}
+
+/// Add a message to the full-text index.
+///
+/// Must be called after the message's content or nick changed, or after the
+/// message was inserted. Does nothing if the message doesn't exist.
+fn fts_add_msg(tx: &Transaction<'_>, room: &RoomIdentifier, id: MessageId) -> rusqlite::Result<()> {
+    tx.prepare_cached(
+        "
+        INSERT INTO euph_msgs_fts (rowid, content, name)
+        SELECT rowid, content, name
+        FROM euph_msgs
+        WHERE domain = ?
+        AND room = ?
+        AND id = ?
+        ",
+    )?
+    .execute(params![room.domain, room.name, WSnowflake(id.0)])?;
+    Ok(())
+}
+
+/// Messages older than this id were pruned and must not be added to the vault
+/// again.
+fn pruned_before(conn: &Connection, room: &RoomIdentifier) -> rusqlite::Result<Option<MessageId>> {
+    let pruned_before = conn
+        .prepare_cached(
+            "
+            SELECT pruned_before
+            FROM euph_rooms
+            WHERE domain = ?
+            AND room = ?
+            ",
+        )?
+        .query_row([&room.domain, &room.name], |row| {
+            row.get::<_, Option<WSnowflake>>(0)
+        })
+        .optional()?
+        .flatten()
+        .map(|s| MessageId(s.0));
+    Ok(pruned_before)
+}
+
+/// Insert or update messages.
+///
+/// Messages that were pruned are skipped. Returns the amount of messages that
+/// were inserted or updated.
+fn insert_msgs(
+    tx: &Transaction<'_>,
+    room: &RoomIdentifier,
+    own_user_id: &Option<UserId>,
+    msgs: Vec<Message>,
+) -> rusqlite::Result<usize> {
+    let pruned_before = pruned_before(tx, room)?;
+    let msgs = msgs
+        .into_iter()
+        .filter(|msg| pruned_before.is_none_or(|id| msg.id >= id))
+        .collect::<Vec<_>>();
+    let amount = msgs.len();
+
+    let mut insert_msg = tx.prepare(
+        "
+        INSERT INTO euph_msgs (
+            domain, room,
+            id, parent, previous_edit_id, time, content, encryption_key_id, edited, deleted, truncated,
+            user_id, name, server_id, server_era, session_id, is_staff, is_manager, client_address, real_client_address,
+            seen
+        )
+        VALUES (
+            :domain, :room,
+            :id, :parent, :previous_edit_id, :time, :content, :encryption_key_id, :edited, :deleted, :truncated,
+            :user_id, :name, :server_id, :server_era, :session_id, :is_staff, :is_manager, :client_address, :real_client_address,
+            (:user_id == :own_user_id OR EXISTS(
+                SELECT 1
+                FROM euph_rooms
+                WHERE domain = :domain
+                AND room = :room
+                AND :time < first_joined
+            ))
+        )
+        ON CONFLICT (domain, room, id) DO UPDATE
+        SET
+            domain = :domain,
+            room = :room,
+            id = :id,
+            parent = :parent,
+            previous_edit_id = :previous_edit_id,
+            time = :time,
+            content = :content,
+            encryption_key_id = :encryption_key_id,
+            edited = :edited,
+            deleted = :deleted,
+            truncated = :truncated,
+
+            user_id = :user_id,
+            name = :name,
+            server_id = :server_id,
+            server_era = :server_era,
+            session_id = :session_id,
+            is_staff = :is_staff,
+            is_manager = :is_manager,
+            client_address = :client_address,
+            real_client_address = :real_client_address
+        "
+    )?;
+
+    let own_user_id = own_user_id.as_ref().map(|u| &u.0);
+    for msg in msgs {
+        let id = msg.id;
+        fts_remove_msg(tx, room, id)?;
+        insert_msg.execute(named_params! {
+            ":domain": room.domain,
+            ":room": room.name,
+            ":id": WSnowflake(msg.id.0),
+            ":parent": msg.parent.map(|id| WSnowflake(id.0)),
+            ":previous_edit_id": msg.previous_edit_id.map(WSnowflake),
+            ":time": WTime(msg.time),
+            ":content": msg.content,
+            ":encryption_key_id": msg.encryption_key_id,
+            ":edited": msg.edited.map(WTime),
+            ":deleted": msg.deleted.map(WTime),
+            ":truncated": msg.truncated,
+            ":user_id": msg.sender.id.0,
+            ":name": msg.sender.name,
+            ":server_id": msg.sender.server_id,
+            ":server_era": msg.sender.server_era,
+            ":session_id": msg.sender.session_id.0,
+            ":is_staff": msg.sender.is_staff,
+            ":is_manager": msg.sender.is_manager,
             ":client_address": msg.sender.client_address,
             ":real_client_address": msg.sender.real_client_address,
             ":own_user_id": own_user_id, // May be NULL
         })?;
+        fts_add_msg(tx, room, id)?;
     }
 
-    Ok(())
+    Ok(amount)
 }
 
 fn add_span(
@@ -429,6 +1387,14 @@ fn add_span(
     room: &RoomIdentifier,
     start: Option<MessageId>,
     end: Option<MessageId>,
+) -> rusqlite::Result<()> {
+    add_spans(tx, room, vec![(start, end)])
+}
+
+fn add_spans(
+    tx: &Transaction<'_>,
+    room: &RoomIdentifier,
+    new_spans: Vec<(Option<MessageId>, Option<MessageId>)>,
 ) -> rusqlite::Result<()> {
     // Retrieve all spans for the room
     let mut spans = tx
@@ -439,136 +1405,589 @@ fn add_span(
             WHERE domain = ?
             AND room = ?
             ",
-        )?
-        .query_map([&room.domain, &room.name], |row| {
-            let start = row.get::<_, Option<WSnowflake>>(0)?.map(|s| MessageId(s.0));
-            let end = row.get::<_, Option<WSnowflake>>(1)?.map(|s| MessageId(s.0));
-            Ok((start, end))
-        })?
-        .collect::<Result<Vec<_>, _>>()?;
+        )?
+        .query_map([&room.domain, &room.name], |row| {
+            let start = row.get::<_, Option<WSnowflake>>(0)?.map(|s| MessageId(s.0));
+            let end = row.get::<_, Option<WSnowflake>>(1)?.map(|s| MessageId(s.0));
+            Ok((start, end))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Add new spans and sort spans lexicographically
+    spans.extend(new_spans);
+    spans.sort_unstable();
+
+    // Combine overlapping spans (including newly added spans)
+    let mut cur_span: Option<(Option<MessageId>, Option<MessageId>)> = None;
+    let mut result = vec![];
+    for mut span in spans {
+        if let Some(cur_span) = &mut cur_span {
+            if span.0 <= cur_span.1 {
+                // Since spans are sorted lexicographically, we know that
+                // cur_span.0 <= span.0, which means that span starts inside
+                // of cur_span.
+                cur_span.1 = cur_span.1.max(span.1);
+            } else {
+                // Since span doesn't overlap cur_span, we know that no
+                // later span will overlap cur_span either. The size of
+                // cur_span is thus final.
+                mem::swap(cur_span, &mut span);
+                result.push(span);
+            }
+        } else {
+            cur_span = Some(span);
+        }
+    }
+    if let Some(cur_span) = cur_span {
+        result.push(cur_span);
+    }
+
+    // Pruned messages are never added again, so spans must not cover them
+    // either. This mirrors how spans are shrunk when pruning.
+    if let Some(pruned_before) = pruned_before(tx, room)? {
+        let pruned_before = Some(pruned_before);
+        result.retain(|(_, end)| end.is_none() || *end >= pruned_before);
+        for (start, end) in &mut result {
+            if end.is_some() && *start < pruned_before {
+                *start = pruned_before;
+            }
+        }
+    }
+
+    // Delete all spans for the room
+    tx.execute(
+        "
+        DELETE FROM euph_spans
+        WHERE domain = ?
+        AND room = ?
+        ",
+        [&room.domain, &room.name],
+    )?;
+
+    // Re-insert combined spans for the room
+    let mut stmt = tx.prepare(
+        "
+        INSERT INTO euph_spans (domain, room, start, end)
+        VALUES (?, ?, ?, ?)
+        ",
+    )?;
+    for (start, end) in result {
+        stmt.execute(params![
+            room.domain,
+            room.name,
+            start.map(|id| WSnowflake(id.0)),
+            end.map(|id| WSnowflake(id.0))
+        ])?;
+    }
+
+    Ok(())
+}
+
+impl Action for AddMsg {
+    type Output = ();
+    type Error = rusqlite::Error;
+
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        let tx = conn.transaction()?;
+
+        let end = self.msg.id;
+        insert_msgs(&tx, &self.room, &self.own_user_id, vec![*self.msg])?;
+        add_span(&tx, &self.room, self.prev_msg_id, Some(end))?;
+
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+impl Action for AddMsgs {
+    type Output = ();
+    type Error = rusqlite::Error;
+
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        let tx = conn.transaction()?;
+
+        if self.msgs.is_empty() {
+            add_span(&tx, &self.room, None, self.next_msg_id)?;
+        } else {
+            let first_msg_id = self.msgs.first().unwrap().id;
+            let last_msg_id = self.msgs.last().unwrap().id;
+
+            insert_msgs(&tx, &self.room, &self.own_user_id, self.msgs)?;
+
+            let end = self.next_msg_id.unwrap_or(last_msg_id);
+            add_span(&tx, &self.room, Some(first_msg_id), Some(end))?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+impl Action for ImportMsgs {
+    type Output = usize;
+    type Error = rusqlite::Error;
+
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        let tx = conn.transaction()?;
+
+        // Imported messages predate the room's first join if the room isn't
+        // known yet, so they are all marked as seen.
+        tx.execute(
+            "
+            INSERT INTO euph_rooms (domain, room, first_joined, last_joined)
+            VALUES (:domain, :room, :time, :time)
+            ON CONFLICT (domain, room) DO NOTHING
+            ",
+            named_params! {
+                ":domain": self.room.domain,
+                ":room": self.room.name,
+                ":time": WTime(self.time),
+            },
+        )?;
+
+        let last_msg_id = self.msgs.iter().map(|msg| msg.id).max();
+
+        // Messages that are already known may be more up to date than the
+        // imported ones and are left untouched.
+        let mut new_msgs = vec![];
+        {
+            let mut known = tx.prepare(
+                "
+                SELECT EXISTS(
+                    SELECT 1
+                    FROM euph_msgs
+                    WHERE domain = ?
+                    AND room = ?
+                    AND id = ?
+                )
+                ",
+            )?;
+            for msg in self.msgs {
+                let exists = known.query_row(
+                    params![self.room.domain, self.room.name, WSnowflake(msg.id.0)],
+                    |row| row.get::<_, bool>(0),
+                )?;
+                if !exists {
+                    new_msgs.push(msg);
+                }
+            }
+        }
+
+        let amount = insert_msgs(&tx, &self.room, &None, new_msgs)?;
+
+        // Without further knowledge, the only thing we know for sure is that
+        // the imported messages exist, not that there are no other messages
+        // between them. In that case, no spans are added and the client will
+        // download the surrounding history later.
+        if self.complete_history
+            && let Some(last_msg_id) = last_msg_id
+        {
+            add_span(&tx, &self.room, None, Some(last_msg_id))?;
+        }
+
+        tx.commit()?;
+        Ok(amount)
+    }
+}
+
+impl Action for Prune {
+    type Output = usize;
+    type Error = rusqlite::Error;
+
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        let tx = conn.transaction()?;
+
+        // Newest message that should be deleted
+        let mut last_pruned = None;
+
+        if let Some(time) = self.older_than {
+            let id = tx
+                .query_row(
+                    "
+                    SELECT MAX(id)
+                    FROM euph_msgs
+                    WHERE domain = ?
+                    AND room = ?
+                    AND time < ?
+                    ",
+                    params![self.room.domain, self.room.name, WTime(time)],
+                    |row| row.get::<_, Option<WSnowflake>>(0),
+                )?
+                .map(|s| MessageId(s.0));
+            last_pruned = last_pruned.max(id);
+        }
+
+        if let Some(amount) = self.keep_newest {
+            let id = tx
+                .query_row(
+                    "
+                    SELECT id
+                    FROM euph_msgs
+                    WHERE domain = ?
+                    AND room = ?
+                    ORDER BY id DESC
+                    LIMIT 1 OFFSET ?
+                    ",
+                    params![self.room.domain, self.room.name, amount],
+                    |row| row.get::<_, WSnowflake>(0),
+                )
+                .optional()?
+                .map(|s| MessageId(s.0));
+            last_pruned = last_pruned.max(id);
+        }
+
+        let Some(last_pruned) = last_pruned else {
+            return Ok(0); // Nothing to prune
+        };
+        let pruned_before = MessageId(Snowflake(last_pruned.0.0 + 1));
+
+        tx.execute(
+            "
+            INSERT INTO euph_msgs_fts (euph_msgs_fts, rowid, content, name)
+            SELECT 'delete', rowid, content, name
+            FROM euph_msgs
+            WHERE domain = ?
+            AND room = ?
+            AND id < ?
+            ",
+            params![
+                self.room.domain,
+                self.room.name,
+                WSnowflake(pruned_before.0)
+            ],
+        )?;
+
+        let pruned = tx.execute(
+            "
+            DELETE FROM euph_msgs
+            WHERE domain = ?
+            AND room = ?
+            AND id < ?
+            ",
+            params![
+                self.room.domain,
+                self.room.name,
+                WSnowflake(pruned_before.0)
+            ],
+        )?;
+
+        // Trees whose messages were all deleted no longer exist. Trees whose
+        // root was deleted but that still contain replies keep the root id.
+        // Replies to deleted messages in the middle of a tree now start their
+        // own trees.
+        tx.execute(
+            "
+            DELETE FROM euph_trees
+            WHERE domain = :domain
+            AND room = :room
+            AND id < :id
+            AND NOT EXISTS(
+                SELECT *
+                FROM euph_msgs
+                WHERE domain = :domain
+                AND room = :room
+                AND parent = euph_trees.id
+            )
+            ",
+            named_params! {
+                ":domain": self.room.domain,
+                ":room": self.room.name,
+                ":id": WSnowflake(pruned_before.0),
+            },
+        )?;
+        tx.execute(
+            "
+            INSERT OR IGNORE INTO euph_trees (domain, room, id)
+            SELECT DISTINCT domain, room, parent
+            FROM euph_msgs
+            WHERE domain = :domain
+            AND room = :room
+            AND parent < :id
+            ",
+            named_params! {
+                ":domain": self.room.domain,
+                ":room": self.room.name,
+                ":id": WSnowflake(pruned_before.0),
+            },
+        )?;
+
+        // Spans must only cover messages that are still in the vault, so they
+        // are shrunk to start after the deleted messages.
+        tx.execute(
+            "
+            DELETE FROM euph_spans
+            WHERE domain = ?
+            AND room = ?
+            AND end < ?
+            ",
+            params![
+                self.room.domain,
+                self.room.name,
+                WSnowflake(pruned_before.0)
+            ],
+        )?;
+        tx.execute(
+            "
+            UPDATE euph_spans
+            SET start = :id
+            WHERE domain = :domain
+            AND room = :room
+            AND (start IS NULL OR start < :id)
+            AND end IS NOT NULL
+            ",
+            named_params! {
+                ":domain": self.room.domain,
+                ":room": self.room.name,
+                ":id": WSnowflake(pruned_before.0),
+            },
+        )?;
 
-    // Add new span and sort spans lexicographically
-    spans.push((start, end));
-    spans.sort_unstable();
+        tx.execute(
+            "
+            UPDATE euph_rooms
+            SET pruned_before = max(COALESCE(pruned_before, 0), :id)
+            WHERE domain = :domain
+            AND room = :room
+            ",
+            named_params! {
+                ":domain": self.room.domain,
+                ":room": self.room.name,
+                ":id": WSnowflake(pruned_before.0),
+            },
+        )?;
 
-    // Combine overlapping spans (including newly added span)
-    let mut cur_span: Option<(Option<MessageId>, Option<MessageId>)> = None;
-    let mut result = vec![];
-    for mut span in spans {
-        if let Some(cur_span) = &mut cur_span {
-            if span.0 <= cur_span.1 {
-                // Since spans are sorted lexicographically, we know that
-                // cur_span.0 <= span.0, which means that span starts inside
-                // of cur_span.
-                cur_span.1 = cur_span.1.max(span.1);
-            } else {
-                // Since span doesn't overlap cur_span, we know that no
-                // later span will overlap cur_span either. The size of
-                // cur_span is thus final.
-                mem::swap(cur_span, &mut span);
-                result.push(span);
-            }
-        } else {
-            cur_span = Some(span);
-        }
-    }
-    if let Some(cur_span) = cur_span {
-        result.push(cur_span);
+        tx.commit()?;
+        Ok(pruned)
     }
+}
 
-    // Delete all spans for the room
-    tx.execute(
-        "
-        DELETE FROM euph_spans
-        WHERE domain = ?
-        AND room = ?
-        ",
-        [&room.domain, &room.name],
-    )?;
+impl Action for GetPrunedBefore {
+    type Output = Option<MessageId>;
+    type Error = rusqlite::Error;
 
-    // Re-insert combined spans for the room
-    let mut stmt = tx.prepare(
-        "
-        INSERT INTO euph_spans (domain, room, start, end)
-        VALUES (?, ?, ?, ?)
-        ",
-    )?;
-    for (start, end) in result {
-        stmt.execute(params![
-            room.domain,
-            room.name,
-            start.map(|id| WSnowflake(id.0)),
-            end.map(|id| WSnowflake(id.0))
-        ])?;
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        pruned_before(conn, &self.room)
     }
+}
 
-    Ok(())
+impl Action for EditMsg {
+    type Output = ();
+    type Error = rusqlite::Error;
+
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        // Edits are only applied to messages that are already known, and only
+        // if they are newer than the last edit applied to the message. Since
+        // the edit id is stored as the message's previous edit id, the next
+        // edit continues the chain.
+        //
+        // The parent is not updated because the tree cache depends on it.
+        let tx = conn.transaction()?;
+        fts_remove_msg(&tx, &self.room, self.msg.id)?;
+        tx.execute(
+            "
+            UPDATE euph_msgs
+            SET
+                previous_edit_id = :edit_id,
+                content = :content,
+                encryption_key_id = :encryption_key_id,
+                edited = :edited,
+                deleted = :deleted,
+                truncated = :truncated
+            WHERE domain = :domain
+            AND room = :room
+            AND id = :id
+            AND (previous_edit_id IS NULL OR previous_edit_id < :edit_id)
+            ",
+            named_params! {
+                ":domain": self.room.domain,
+                ":room": self.room.name,
+                ":id": WSnowflake(self.msg.id.0),
+                ":edit_id": WSnowflake(self.edit_id),
+                ":content": self.msg.content,
+                ":encryption_key_id": self.msg.encryption_key_id,
+                ":edited": self.msg.edited.map(WTime),
+                ":deleted": self.msg.deleted.map(WTime),
+                ":truncated": self.msg.truncated,
+            },
+        )?;
+        fts_add_msg(&tx, &self.room, self.msg.id)?;
+        tx.commit()?;
+        Ok(())
+    }
 }
 
-impl Action for AddMsg {
+impl Action for MarkDeleted {
     type Output = ();
     type Error = rusqlite::Error;
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
         let tx = conn.transaction()?;
 
-        let end = self.msg.id;
-        insert_msgs(&tx, &self.room, &self.own_user_id, vec![*self.msg])?;
-        add_span(&tx, &self.room, self.prev_msg_id, Some(end))?;
+        {
+            let mut mark_deleted = tx.prepare(
+                "
+                UPDATE euph_msgs
+                SET deleted = :time
+                WHERE domain = :domain
+                AND room = :room
+                AND id = :id
+                AND deleted IS NULL
+                ",
+            )?;
+
+            for id in self.ids {
+                mark_deleted.execute(named_params! {
+                    ":domain": self.room.domain,
+                    ":room": self.room.name,
+                    ":id": WSnowflake(id.0),
+                    ":time": WTime(self.time),
+                })?;
+            }
+        }
 
         tx.commit()?;
         Ok(())
     }
 }
 
-impl Action for AddMsgs {
+impl Action for UpdateMsg {
     type Output = ();
     type Error = rusqlite::Error;
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        // Only messages that are already known are updated, since inserting
+        // them here would leave them outside of any span.
         let tx = conn.transaction()?;
+        fts_remove_msg(&tx, &self.room, self.msg.id)?;
+        tx.execute(
+            "
+            UPDATE euph_msgs
+            SET
+                previous_edit_id = :previous_edit_id,
+                content = :content,
+                encryption_key_id = :encryption_key_id,
+                edited = :edited,
+                deleted = :deleted,
+                truncated = :truncated
+            WHERE domain = :domain
+            AND room = :room
+            AND id = :id
+            ",
+            named_params! {
+                ":domain": self.room.domain,
+                ":room": self.room.name,
+                ":id": WSnowflake(self.msg.id.0),
+                ":previous_edit_id": self.msg.previous_edit_id.map(WSnowflake),
+                ":content": self.msg.content,
+                ":encryption_key_id": self.msg.encryption_key_id,
+                ":edited": self.msg.edited.map(WTime),
+                ":deleted": self.msg.deleted.map(WTime),
+                ":truncated": self.msg.truncated,
+            },
+        )?;
+        fts_add_msg(&tx, &self.room, self.msg.id)?;
+        tx.commit()?;
+        Ok(())
+    }
+}
 
-        if self.msgs.is_empty() {
-            add_span(&tx, &self.room, None, self.next_msg_id)?;
-        } else {
-            let first_msg_id = self.msgs.first().unwrap().id;
-            let last_msg_id = self.msgs.last().unwrap().id;
+impl Action for GetTruncatedMsgIds {
+    type Output = Vec<MessageId>;
+    type Error = rusqlite::Error;
 
-            insert_msgs(&tx, &self.room, &self.own_user_id, self.msgs)?;
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        let mut is_truncated = conn.prepare(
+            "
+            SELECT truncated
+            FROM euph_msgs
+            WHERE domain = ?
+            AND room = ?
+            AND id = ?
+            ",
+        )?;
 
-            let end = self.next_msg_id.unwrap_or(last_msg_id);
-            add_span(&tx, &self.room, Some(first_msg_id), Some(end))?;
+        let mut ids = vec![];
+        for id in self.prioritized {
+            let truncated = is_truncated
+                .query_row(
+                    params![self.room.domain, self.room.name, WSnowflake(id.0)],
+                    |row| row.get::<_, bool>(0),
+                )
+                .optional()?
+                .unwrap_or(false);
+            if truncated && !ids.contains(&id) {
+                ids.push(id);
+            }
         }
 
-        tx.commit()?;
-        Ok(())
+        let newest = conn
+            .prepare(
+                "
+                SELECT id
+                FROM euph_msgs
+                WHERE domain = ?
+                AND room = ?
+                AND truncated
+                ORDER BY id DESC
+                LIMIT ?
+                ",
+            )?
+            .query_map(
+                params![self.room.domain, self.room.name, self.amount],
+                |row| row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0)),
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        for id in newest {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+
+        Ok(ids)
     }
 }
 
-impl Action for GetLastSpan {
-    type Output = Option<(Option<MessageId>, Option<MessageId>)>;
+impl Action for GetGaps {
+    type Output = Vec<(Option<MessageId>, MessageId)>;
     type Error = rusqlite::Error;
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
-        let span = conn
+        let spans = conn
             .prepare(
                 "
                 SELECT start, end
                 FROM euph_spans
                 WHERE domain = ?
                 AND room = ?
-                ORDER BY start DESC
-                LIMIT 1
+                ORDER BY start ASC
                 ",
             )?
-            .query_row([&self.room.domain, &self.room.name], |row| {
+            .query_map([&self.room.domain, &self.room.name], |row| {
                 Ok((
                     row.get::<_, Option<WSnowflake>>(0)?.map(|s| MessageId(s.0)),
                     row.get::<_, Option<WSnowflake>>(1)?.map(|s| MessageId(s.0)),
                 ))
-            })
-            .optional()?;
-        Ok(span)
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let pruned_before = pruned_before(conn, &self.room)?;
+
+        // Spans are merged whenever they are added, so they never overlap or
+        // touch. This means there is a gap before every span, unless the span
+        // starts at the beginning of the room's history.
+        let mut gaps = vec![];
+        let mut prev_end = None;
+        for (start, end) in spans {
+            // Gaps consisting only of pruned messages are not gaps
+            if let Some(start) = start
+                && pruned_before.is_none_or(|id| start > id)
+            {
+                gaps.push((prev_end, start));
+            }
+            prev_end = end;
+        }
+        Ok(gaps)
     }
 }
 
@@ -611,7 +2030,7 @@ impl Action for GetMsg {
         let msg = conn
             .query_row(
                 "
-                SELECT id, parent, time, user_id, name, content, seen
+                SELECT id, parent, time, user_id, name, content, seen, deleted IS NOT NULL
                 FROM euph_msgs
                 WHERE domain = ?
                 AND room = ?
@@ -627,6 +2046,7 @@ impl Action for GetMsg {
                         nick: row.get(4)?,
                         content: row.get(5)?,
                         seen: row.get(6)?,
+                        deleted: row.get(7)?,
                     })
                 },
             )
@@ -655,30 +2075,7 @@ impl Action for GetFullMsg {
         let msg = query
             .query_row(
                 params![self.room.domain, self.room.name, WSnowflake(self.id.0)],
-                |row| {
-                    Ok(Message {
-                        id: MessageId(row.get::<_, WSnowflake>(0)?.0),
-                        parent: row.get::<_, Option<WSnowflake>>(1)?.map(|s| MessageId(s.0)),
-                        previous_edit_id: row.get::<_, Option<WSnowflake>>(2)?.map(|s| s.0),
-                        time: row.get::<_, WTime>(3)?.0,
-                        content: row.get(4)?,
-                        encryption_key_id: row.get(5)?,
-                        edited: row.get::<_, Option<WTime>>(6)?.map(|t| t.0),
-                        deleted: row.get::<_, Option<WTime>>(7)?.map(|t| t.0),
-                        truncated: row.get(8)?,
-                        sender: SessionView {
-                            id: UserId(row.get(9)?),
-                            name: row.get(10)?,
-                            server_id: row.get(11)?,
-                            server_era: row.get(12)?,
-                            session_id: SessionId(row.get(13)?),
-                            is_staff: row.get(14)?,
-                            is_manager: row.get(15)?,
-                            client_address: row.get(16)?,
-                            real_client_address: row.get(17)?,
-                        },
-                    })
-                },
+                row_to_msg,
             )
             .optional()?;
         Ok(msg)
@@ -691,7 +2088,7 @@ impl Action for GetTree {
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
         let msgs = conn
-            .prepare(
+            .prepare(&format!(
                 "
                 WITH RECURSIVE
                 tree (domain, room, id) AS (
@@ -704,14 +2101,20 @@ impl Action for GetTree {
                         AND tree.room = euph_msgs.room
                         AND tree.id = euph_msgs.parent
                 )
-                SELECT id, parent, time, user_id, name, content, seen
+                SELECT id, parent, time, user_id, name, content, seen, deleted IS NOT NULL
                 FROM euph_msgs
                 JOIN tree USING (domain, room, id)
+                WHERE NOT {HIDDEN}
                 ORDER BY id ASC
-                ",
-            )?
+                "
+            ))?
             .query_map(
-                params![self.room.domain, self.room.name, WSnowflake(self.root_id.0)],
+                params![
+                    self.room.domain,
+                    self.room.name,
+                    WSnowflake(self.root_id.0),
+                    self.hide_deleted,
+                ],
                 |row| {
                     Ok(SmallMessage {
                         id: MessageId(row.get::<_, WSnowflake>(0)?.0),
@@ -721,6 +2124,7 @@ impl Action for GetTree {
                         nick: row.get(4)?,
                         content: row.get(5)?,
                         seen: row.get(6)?,
+                        deleted: row.get(7)?,
                     })
                 },
             )?
@@ -735,19 +2139,21 @@ impl Action for GetFirstRootId {
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
         let root_id = conn
-            .prepare(
+            .prepare(&format!(
                 "
                 SELECT id
                 FROM euph_trees
                 WHERE domain = ?
                 AND room = ?
+                AND NOT {ROOT_HIDDEN}
                 ORDER BY id ASC
                 LIMIT 1
-                ",
-            )?
-            .query_row([&self.room.domain, &self.room.name], |row| {
-                row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0))
-            })
+                "
+            ))?
+            .query_row(
+                params![self.room.domain, self.room.name, self.hide_deleted],
+                |row| row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0)),
+            )
             .optional()?;
         Ok(root_id)
     }
@@ -759,19 +2165,21 @@ impl Action for GetLastRootId {
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
         let root_id = conn
-            .prepare(
+            .prepare(&format!(
                 "
                 SELECT id
                 FROM euph_trees
                 WHERE domain = ?
                 AND room = ?
+                AND NOT {ROOT_HIDDEN}
                 ORDER BY id DESC
                 LIMIT 1
-                ",
-            )?
-            .query_row([&self.room.domain, &self.room.name], |row| {
-                row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0))
-            })
+                "
+            ))?
+            .query_row(
+                params![self.room.domain, self.room.name, self.hide_deleted],
+                |row| row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0)),
+            )
             .optional()?;
         Ok(root_id)
     }
@@ -783,19 +2191,25 @@ impl Action for GetPrevRootId {
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
         let root_id = conn
-            .prepare(
+            .prepare(&format!(
                 "
                 SELECT id
                 FROM euph_trees
                 WHERE domain = ?
                 AND room = ?
                 AND id < ?
+                AND NOT {ROOT_HIDDEN}
                 ORDER BY id DESC
                 LIMIT 1
-                ",
-            )?
+                "
+            ))?
             .query_row(
-                params![self.room.domain, self.room.name, WSnowflake(self.root_id.0)],
+                params![
+                    self.room.domain,
+                    self.room.name,
+                    WSnowflake(self.root_id.0),
+                    self.hide_deleted,
+                ],
                 |row| row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0)),
             )
             .optional()?;
@@ -809,19 +2223,25 @@ impl Action for GetNextRootId {
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
         let root_id = conn
-            .prepare(
+            .prepare(&format!(
                 "
                 SELECT id
                 FROM euph_trees
                 WHERE domain = ?
                 AND room = ?
                 AND id > ?
+                AND NOT {ROOT_HIDDEN}
                 ORDER BY id ASC
                 LIMIT 1
-                ",
-            )?
+                "
+            ))?
             .query_row(
-                params![self.room.domain, self.room.name, WSnowflake(self.root_id.0)],
+                params![
+                    self.room.domain,
+                    self.room.name,
+                    WSnowflake(self.root_id.0),
+                    self.hide_deleted,
+                ],
                 |row| row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0)),
             )
             .optional()?;
@@ -835,19 +2255,21 @@ impl Action for GetOldestMsgId {
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
         let msg_id = conn
-            .prepare(
+            .prepare(&format!(
                 "
                 SELECT id
                 FROM euph_msgs
                 WHERE domain = ?
                 AND room = ?
+                AND NOT {HIDDEN}
                 ORDER BY id ASC
                 LIMIT 1
-                ",
-            )?
-            .query_row([&self.room.domain, &self.room.name], |row| {
-                row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0))
-            })
+                "
+            ))?
+            .query_row(
+                params![self.room.domain, self.room.name, self.hide_deleted],
+                |row| row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0)),
+            )
             .optional()?;
         Ok(msg_id)
     }
@@ -859,19 +2281,21 @@ impl Action for GetNewestMsgId {
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
         let msg_id = conn
-            .prepare(
+            .prepare(&format!(
                 "
                 SELECT id
                 FROM euph_msgs
                 WHERE domain = ?
                 AND room = ?
+                AND NOT {HIDDEN}
                 ORDER BY id DESC
                 LIMIT 1
-                ",
-            )?
-            .query_row([&self.room.domain, &self.room.name], |row| {
-                row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0))
-            })
+                "
+            ))?
+            .query_row(
+                params![self.room.domain, self.room.name, self.hide_deleted],
+                |row| row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0)),
+            )
             .optional()?;
         Ok(msg_id)
     }
@@ -883,44 +2307,57 @@ impl Action for GetOlderMsgId {
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
         let msg_id = conn
-            .prepare(
+            .prepare(&format!(
                 "
                 SELECT id
                 FROM euph_msgs
                 WHERE domain = ?
                 AND room = ?
                 AND id < ?
+                AND NOT {HIDDEN}
                 ORDER BY id DESC
                 LIMIT 1
-                ",
-            )?
+                "
+            ))?
             .query_row(
-                params![self.room.domain, self.room.name, WSnowflake(self.id.0)],
+                params![
+                    self.room.domain,
+                    self.room.name,
+                    WSnowflake(self.id.0),
+                    self.hide_deleted,
+                ],
                 |row| row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0)),
             )
             .optional()?;
         Ok(msg_id)
     }
 }
+
 impl Action for GetNewerMsgId {
     type Output = Option<MessageId>;
     type Error = rusqlite::Error;
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
         let msg_id = conn
-            .prepare(
+            .prepare(&format!(
                 "
                 SELECT id
                 FROM euph_msgs
                 WHERE domain = ?
                 AND room = ?
                 AND id > ?
+                AND NOT {HIDDEN}
                 ORDER BY id ASC
                 LIMIT 1
-                ",
-            )?
+                "
+            ))?
             .query_row(
-                params![self.room.domain, self.room.name, WSnowflake(self.id.0)],
+                params![
+                    self.room.domain,
+                    self.room.name,
+                    WSnowflake(self.id.0),
+                    self.hide_deleted,
+                ],
                 |row| row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0)),
             )
             .optional()?;
@@ -934,20 +2371,22 @@ impl Action for GetOldestUnseenMsgId {
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
         let msg_id = conn
-            .prepare(
+            .prepare(&format!(
                 "
                 SELECT id
                 FROM euph_msgs
                 WHERE domain = ?
                 AND room = ?
                 AND NOT seen
+                AND NOT {HIDDEN}
                 ORDER BY id ASC
                 LIMIT 1
-                ",
-            )?
-            .query_row([&self.room.domain, &self.room.name], |row| {
-                row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0))
-            })
+                "
+            ))?
+            .query_row(
+                params![self.room.domain, self.room.name, self.hide_deleted],
+                |row| row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0)),
+            )
             .optional()?;
         Ok(msg_id)
     }
@@ -959,20 +2398,22 @@ impl Action for GetNewestUnseenMsgId {
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
         let msg_id = conn
-            .prepare(
+            .prepare(&format!(
                 "
                 SELECT id
                 FROM euph_msgs
                 WHERE domain = ?
                 AND room = ?
-                AND NOT seen
-                ORDER BY id DESC
-                LIMIT 1
-                ",
-            )?
-            .query_row([&self.room.domain, &self.room.name], |row| {
-                row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0))
-            })
+                AND NOT seen
+                AND NOT {HIDDEN}
+                ORDER BY id DESC
+                LIMIT 1
+                "
+            ))?
+            .query_row(
+                params![self.room.domain, self.room.name, self.hide_deleted],
+                |row| row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0)),
+            )
             .optional()?;
         Ok(msg_id)
     }
@@ -984,7 +2425,7 @@ impl Action for GetOlderUnseenMsgId {
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
         let msg_id = conn
-            .prepare(
+            .prepare(&format!(
                 "
                 SELECT id
                 FROM euph_msgs
@@ -992,12 +2433,18 @@ impl Action for GetOlderUnseenMsgId {
                 AND room = ?
                 AND NOT seen
                 AND id < ?
+                AND NOT {HIDDEN}
                 ORDER BY id DESC
                 LIMIT 1
-                ",
-            )?
+                "
+            ))?
             .query_row(
-                params![self.room.domain, self.room.name, WSnowflake(self.id.0)],
+                params![
+                    self.room.domain,
+                    self.room.name,
+                    WSnowflake(self.id.0),
+                    self.hide_deleted,
+                ],
                 |row| row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0)),
             )
             .optional()?;
@@ -1011,7 +2458,7 @@ impl Action for GetNewerUnseenMsgId {
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
         let msg_id = conn
-            .prepare(
+            .prepare(&format!(
                 "
                 SELECT id
                 FROM euph_msgs
@@ -1019,12 +2466,18 @@ impl Action for GetNewerUnseenMsgId {
                 AND room = ?
                 AND NOT seen
                 AND id > ?
+                AND NOT {HIDDEN}
                 ORDER BY id ASC
                 LIMIT 1
-                ",
-            )?
+                "
+            ))?
             .query_row(
-                params![self.room.domain, self.room.name, WSnowflake(self.id.0)],
+                params![
+                    self.room.domain,
+                    self.room.name,
+                    WSnowflake(self.id.0),
+                    self.hide_deleted,
+                ],
                 |row| row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0)),
             )
             .optional()?;
@@ -1038,15 +2491,26 @@ impl Action for GetUnseenMsgsCount {
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
         let amount = conn
-            .prepare(
+            .prepare(&format!(
                 "
-                SELECT amount
+                SELECT amount - (
+                    SELECT COUNT(*)
+                    FROM euph_msgs
+                    WHERE domain = euph_unseen_counts.domain
+                    AND room = euph_unseen_counts.room
+                    AND NOT seen
+                    AND deleted IS NOT NULL
+                    AND {HIDDEN}
+                )
                 FROM euph_unseen_counts
                 WHERE domain = ?
                 AND room = ?
-                ",
-            )?
-            .query_row(params![self.room.domain, self.room.name], |row| row.get(0))
+                "
+            ))?
+            .query_row(
+                params![self.hide_deleted, self.room.domain, self.room.name],
+                |row| row.get(0),
+            )
             .optional()?
             .unwrap_or(0);
         Ok(amount)
@@ -1107,31 +2571,6 @@ impl Action for GetChunkAfter {
     type Error = rusqlite::Error;
 
     fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
-        fn row2msg(row: &Row<'_>) -> rusqlite::Result<Message> {
-            Ok(Message {
-                id: MessageId(row.get::<_, WSnowflake>(0)?.0),
-                parent: row.get::<_, Option<WSnowflake>>(1)?.map(|s| MessageId(s.0)),
-                previous_edit_id: row.get::<_, Option<WSnowflake>>(2)?.map(|s| s.0),
-                time: row.get::<_, WTime>(3)?.0,
-                content: row.get(4)?,
-                encryption_key_id: row.get(5)?,
-                edited: row.get::<_, Option<WTime>>(6)?.map(|t| t.0),
-                deleted: row.get::<_, Option<WTime>>(7)?.map(|t| t.0),
-                truncated: row.get(8)?,
-                sender: SessionView {
-                    id: UserId(row.get(9)?),
-                    name: row.get(10)?,
-                    server_id: row.get(11)?,
-                    server_era: row.get(12)?,
-                    session_id: SessionId(row.get(13)?),
-                    is_staff: row.get(14)?,
-                    is_manager: row.get(15)?,
-                    client_address: row.get(16)?,
-                    real_client_address: row.get(17)?,
-                },
-            })
-        }
-
         let messages = if let Some(id) = self.id {
             conn.prepare("
                 SELECT
@@ -1144,7 +2583,7 @@ impl Action for GetChunkAfter {
                 ORDER BY id ASC
                 LIMIT ?
             ")?
-            .query_map(params![self.room.domain, self.room.name, WSnowflake(id.0), self.amount], row2msg)?
+            .query_map(params![self.room.domain, self.room.name, WSnowflake(id.0), self.amount], row_to_msg)?
             .collect::<rusqlite::Result<_>>()?
         } else {
             conn.prepare("
@@ -1157,7 +2596,7 @@ impl Action for GetChunkAfter {
                 ORDER BY id ASC
                 LIMIT ?
             ")?
-            .query_map(params![self.room.domain, self.room.name, self.amount], row2msg)?
+            .query_map(params![self.room.domain, self.room.name, self.amount], row_to_msg)?
             .collect::<rusqlite::Result<_>>()?
         };
 
@@ -1165,6 +2604,156 @@ impl Action for GetChunkAfter {
     }
 }
 
+impl Action for GetChunkBefore {
+    type Output = Vec<Message>;
+    type Error = rusqlite::Error;
+
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        let mut messages = if let Some(id) = self.id {
+            conn.prepare("
+                SELECT
+                    id, parent, previous_edit_id, time, content, encryption_key_id, edited, deleted, truncated,
+                    user_id, name, server_id, server_era, session_id, is_staff, is_manager, client_address, real_client_address
+                FROM euph_msgs
+                WHERE domain = ?
+                AND room = ?
+                AND id < ?
+                ORDER BY id DESC
+                LIMIT ?
+            ")?
+            .query_map(params![self.room.domain, self.room.name, WSnowflake(id.0), self.amount], row_to_msg)?
+            .collect::<rusqlite::Result<Vec<_>>>()?
+        } else {
+            conn.prepare("
+                SELECT
+                    id, parent, previous_edit_id, time, content, encryption_key_id, edited, deleted, truncated,
+                    user_id, name, server_id, server_era, session_id, is_staff, is_manager, client_address, real_client_address
+                FROM euph_msgs
+                WHERE domain = ?
+                AND room = ?
+                ORDER BY id DESC
+                LIMIT ?
+            ")?
+            .query_map(params![self.room.domain, self.room.name, self.amount], row_to_msg)?
+            .collect::<rusqlite::Result<Vec<_>>>()?
+        };
+
+        // Like the log replies from the server, chunks are ordered oldest first
+        messages.reverse();
+
+        Ok(messages)
+    }
+}
+
+/// Turn user input into an FTS5 query.
+///
+/// Every whitespace-separated term is quoted so that FTS5 syntax in the input
+/// has no special meaning, and the last term is treated as a prefix.
+fn fts_query(query: &str) -> String {
+    let terms = query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect::<Vec<_>>();
+
+    let mut query = terms.join(" ");
+    if !query.is_empty() {
+        query.push('*');
+    }
+    query
+}
+
+impl Action for SearchMsgs {
+    type Output = Vec<SmallMessage>;
+    type Error = rusqlite::Error;
+
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        let query = fts_query(&self.query);
+        if query.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let msgs = conn
+            .prepare(&format!(
+                "
+                SELECT id, parent, time, user_id, euph_msgs.name, euph_msgs.content, seen, deleted IS NOT NULL
+                FROM euph_msgs_fts
+                JOIN euph_msgs ON euph_msgs.rowid = euph_msgs_fts.rowid
+                WHERE euph_msgs_fts MATCH ?
+                AND domain = ?
+                AND room = ?
+                AND NOT {HIDDEN}
+                ORDER BY id DESC
+                LIMIT ?
+                "
+            ))?
+            .query_map(
+                params![
+                    query,
+                    self.room.domain,
+                    self.room.name,
+                    self.hide_deleted,
+                    self.amount,
+                ],
+                |row| {
+                    Ok(SmallMessage {
+                        id: MessageId(row.get::<_, WSnowflake>(0)?.0),
+                        parent: row.get::<_, Option<WSnowflake>>(1)?.map(|s| MessageId(s.0)),
+                        time: row.get::<_, WTime>(2)?.0,
+                        user_id: UserId(row.get(3)?),
+                        nick: row.get(4)?,
+                        content: row.get(5)?,
+                        seen: row.get(6)?,
+                        deleted: row.get(7)?,
+                    })
+                },
+            )?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(msgs)
+    }
+}
+
+impl Action for GetFilteredChunkAfter {
+    type Output = Vec<Message>;
+    type Error = rusqlite::Error;
+
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        let messages = conn
+            .prepare(
+                "
+                SELECT
+                    id, parent, previous_edit_id, time, content, encryption_key_id, edited, deleted, truncated,
+                    user_id, name, server_id, server_era, session_id, is_staff, is_manager, client_address, real_client_address
+                FROM euph_msgs
+                WHERE domain = :domain
+                AND room = :room
+                AND (:id IS NULL OR id > :id)
+                AND (:nick IS NULL OR name = :nick)
+                AND (:user_id IS NULL OR user_id = :user_id)
+                AND (:since IS NULL OR time >= :since)
+                AND (:until IS NULL OR time < :until)
+                ORDER BY id ASC
+                LIMIT :amount
+                ",
+            )?
+            .query_map(
+                named_params! {
+                    ":domain": self.room.domain,
+                    ":room": self.room.name,
+                    ":id": self.id.map(|id| WSnowflake(id.0)),
+                    ":nick": self.filter.nick,
+                    ":user_id": self.filter.user_id.map(|id| id.0),
+                    ":since": self.filter.since.map(WTime),
+                    ":until": self.filter.until.map(WTime),
+                    ":amount": self.amount,
+                },
+                row_to_msg,
+            )?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(messages)
+    }
+}
+
 #[async_trait]
 impl MsgStore<SmallMessage> for EuphRoomVault {
     type Error = vault::tokio::Error<rusqlite::Error>;
@@ -1241,3 +2830,522 @@ impl MsgStore<SmallMessage> for EuphRoomVault {
         self.set_older_seen(*id, seen).await
     }
 }
+
+impl Action for AddBookmark {
+    type Output = ();
+    type Error = rusqlite::Error;
+
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        conn.execute(
+            "
+            INSERT INTO euph_bookmarks (domain, room, id, note, created)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (domain, room, id) DO UPDATE
+            SET note = excluded.note
+            ",
+            params![
+                self.room.domain,
+                self.room.name,
+                WSnowflake(self.id.0),
+                self.note,
+                WTime(self.time),
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+impl Action for RemoveBookmark {
+    type Output = bool;
+    type Error = rusqlite::Error;
+
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        let removed = conn.execute(
+            "
+            DELETE FROM euph_bookmarks
+            WHERE domain = ?
+            AND room = ?
+            AND id = ?
+            ",
+            params![self.room.domain, self.room.name, WSnowflake(self.id.0)],
+        )?;
+        Ok(removed > 0)
+    }
+}
+
+impl Action for GetActiveRootIds {
+    type Output = Vec<MessageId>;
+    type Error = rusqlite::Error;
+
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        // A tree is active during a time range if any of its messages was sent
+        // during that time range.
+        conn.prepare(
+            "
+            WITH RECURSIVE
+            tree (root, id) AS (
+                SELECT id, id
+                FROM euph_trees
+                WHERE domain = :domain
+                AND room = :room
+            UNION ALL
+                SELECT tree.root, euph_msgs.id
+                FROM euph_msgs
+                JOIN tree
+                    ON tree.id = euph_msgs.parent
+                WHERE euph_msgs.domain = :domain
+                AND euph_msgs.room = :room
+            )
+            SELECT DISTINCT tree.root
+            FROM tree
+            JOIN euph_msgs
+                ON euph_msgs.domain = :domain
+                AND euph_msgs.room = :room
+                AND euph_msgs.id = tree.id
+            WHERE (:since IS NULL OR time >= :since)
+            AND (:until IS NULL OR time < :until)
+            ORDER BY tree.root ASC
+            ",
+        )?
+        .query_map(
+            named_params! {
+                ":domain": self.room.domain,
+                ":room": self.room.name,
+                ":since": self.since.map(WTime),
+                ":until": self.until.map(WTime),
+            },
+            |row| row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0)),
+        )?
+        .collect::<rusqlite::Result<_>>()
+    }
+}
+
+impl Action for GetTreeMsgs {
+    type Output = Vec<Message>;
+    type Error = rusqlite::Error;
+
+    fn run(self, conn: &mut Connection) -> Result<Self::Output, Self::Error> {
+        conn.prepare(
+            "
+            WITH RECURSIVE
+            tree (domain, room, id) AS (
+                VALUES (?, ?, ?)
+            UNION
+                SELECT euph_msgs.domain, euph_msgs.room, euph_msgs.id
+                FROM euph_msgs
+                JOIN tree
+                    ON tree.domain = euph_msgs.domain
+                    AND tree.room = euph_msgs.room
+                    AND tree.id = euph_msgs.parent
+            )
+            SELECT
+                id, parent, previous_edit_id, time, content, encryption_key_id, edited, deleted, truncated,
+                user_id, name, server_id, server_era, session_id, is_staff, is_manager, client_address, real_client_address
+            FROM euph_msgs
+            JOIN tree USING (domain, room, id)
+            ORDER BY id ASC
+            "
+        )?
+        .query_map(
+            params![self.room.domain, self.room.name, WSnowflake(self.root_id.0)],
+            row_to_msg,
+        )?
+        .collect::<rusqlite::Result<_>>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euphoxide::api::{Message, MessageId, SessionId, SessionView, Snowflake, Time, UserId};
+    use rusqlite::{Connection, params};
+    use vault::Action;
+
+    use crate::vault::migrate::MIGRATIONS;
+
+    use super::{
+        FsckReport, GetChunkBefore, GetGaps, ImportMsgs, Join, Prune, RoomIdentifier, SearchMsgs,
+        Span, WSnowflake, add_spans, fsck_room, insert_msgs,
+    };
+
+    fn room() -> RoomIdentifier {
+        RoomIdentifier::new("euphoria.leet.nu".to_string(), "test".to_string())
+    }
+
+    fn id(id: u64) -> MessageId {
+        MessageId(Snowflake(id))
+    }
+
+    fn msg(id: u64, parent: Option<u64>) -> Message {
+        Message {
+            id: self::id(id),
+            parent: parent.map(self::id),
+            previous_edit_id: None,
+            time: Time(id as i64),
+            content: format!("hello {id}"),
+            encryption_key_id: None,
+            edited: None,
+            deleted: None,
+            truncated: false,
+            sender: SessionView {
+                id: UserId("agent:test".to_string()),
+                name: "test".to_string(),
+                server_id: "server".to_string(),
+                server_era: "era".to_string(),
+                session_id: SessionId("session".to_string()),
+                is_staff: false,
+                is_manager: false,
+                client_address: None,
+                real_client_address: None,
+            },
+        }
+    }
+
+    /// An in-memory vault containing the room returned by [`room`].
+    fn open() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "foreign_keys", true).unwrap();
+        for (nr, migration) in MIGRATIONS.iter().enumerate() {
+            let mut tx = conn.transaction().unwrap();
+            migration(&mut tx, nr, MIGRATIONS.len()).unwrap();
+            tx.commit().unwrap();
+        }
+
+        Join {
+            room: room(),
+            time: Time(0),
+        }
+        .run(&mut conn)
+        .unwrap();
+
+        conn
+    }
+
+    fn insert(conn: &mut Connection, msgs: Vec<Message>, spans: Vec<Span>) {
+        let tx = conn.transaction().unwrap();
+        insert_msgs(&tx, &room(), &None, msgs).unwrap();
+        add_spans(&tx, &room(), spans).unwrap();
+        tx.commit().unwrap();
+    }
+
+    fn spans(conn: &Connection) -> Vec<Span> {
+        let mut spans = conn
+            .prepare("SELECT start, end FROM euph_spans WHERE domain = ? AND room = ?")
+            .unwrap()
+            .query_map([&room().domain, &room().name], |row| {
+                let start = row.get::<_, Option<WSnowflake>>(0)?.map(|s| MessageId(s.0));
+                let end = row.get::<_, Option<WSnowflake>>(1)?.map(|s| MessageId(s.0));
+                Ok((start, end))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap();
+        spans.sort_unstable();
+        spans
+    }
+
+    fn gaps(conn: &mut Connection) -> Vec<(Option<MessageId>, MessageId)> {
+        GetGaps { room: room() }.run(conn).unwrap()
+    }
+
+    #[test]
+    fn no_gaps_without_spans() {
+        let mut conn = open();
+        assert_eq!(gaps(&mut conn), vec![]);
+
+        // Messages without spans aren't known to be surrounded by gaps either
+        insert(&mut conn, vec![msg(1, None), msg(2, None)], vec![]);
+        assert_eq!(gaps(&mut conn), vec![]);
+    }
+
+    #[test]
+    fn gaps_between_spans() {
+        let mut conn = open();
+        insert(
+            &mut conn,
+            vec![],
+            vec![(Some(id(1)), Some(id(3))), (Some(id(6)), Some(id(10)))],
+        );
+        assert_eq!(gaps(&mut conn), vec![(None, id(1)), (Some(id(3)), id(6))]);
+
+        insert(&mut conn, vec![], vec![(None, Some(id(2)))]);
+        assert_eq!(gaps(&mut conn), vec![(Some(id(3)), id(6))]);
+    }
+
+    #[test]
+    fn chunk_before() {
+        let mut conn = open();
+        insert(&mut conn, (1..=5).map(|id| msg(id, None)).collect(), vec![]);
+
+        let chunk = |conn: &mut Connection, id: Option<MessageId>| {
+            GetChunkBefore {
+                room: room(),
+                id,
+                amount: 2,
+            }
+            .run(conn)
+            .unwrap()
+            .into_iter()
+            .map(|msg| (msg.id, msg.content))
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            chunk(&mut conn, None),
+            vec![
+                (id(4), "hello 4".to_string()),
+                (id(5), "hello 5".to_string())
+            ]
+        );
+        assert_eq!(
+            chunk(&mut conn, Some(id(4))),
+            vec![
+                (id(2), "hello 2".to_string()),
+                (id(3), "hello 3".to_string())
+            ]
+        );
+        assert_eq!(
+            chunk(&mut conn, Some(id(2))),
+            vec![(id(1), "hello 1".to_string())]
+        );
+    }
+
+    #[test]
+    fn add_spans_merges_spans() {
+        let mut conn = open();
+
+        insert(
+            &mut conn,
+            vec![],
+            vec![(Some(id(1)), Some(id(3))), (Some(id(5)), Some(id(7)))],
+        );
+        assert_eq!(
+            spans(&conn),
+            vec![(Some(id(1)), Some(id(3))), (Some(id(5)), Some(id(7)))]
+        );
+
+        // Overlapping and touching spans are merged
+        insert(&mut conn, vec![], vec![(Some(id(2)), Some(id(5)))]);
+        assert_eq!(spans(&conn), vec![(Some(id(1)), Some(id(7)))]);
+
+        // Adjacent ids aren't known to have no messages between them
+        insert(&mut conn, vec![], vec![(Some(id(9)), Some(id(10)))]);
+        assert_eq!(
+            spans(&conn),
+            vec![(Some(id(1)), Some(id(7))), (Some(id(9)), Some(id(10)))]
+        );
+
+        // Spans may extend to the beginning of the room's history
+        insert(&mut conn, vec![], vec![(None, Some(id(1)))]);
+        assert_eq!(
+            spans(&conn),
+            vec![(None, Some(id(7))), (Some(id(9)), Some(id(10)))]
+        );
+    }
+
+    #[test]
+    fn import_adds_spans_only_for_complete_history() {
+        let import = |conn: &mut Connection, msgs: Vec<Message>, complete_history: bool| {
+            ImportMsgs {
+                room: room(),
+                msgs,
+                time: Time(0),
+                complete_history,
+            }
+            .run(conn)
+            .unwrap()
+        };
+
+        let mut conn = open();
+        let msgs = (1..=1000).map(|id| msg(id, None)).collect::<Vec<_>>();
+        assert_eq!(import(&mut conn, msgs, false), 1000);
+        assert_eq!(spans(&conn), vec![]);
+
+        let mut conn = open();
+        let msgs = (1..=1000).map(|id| msg(id, None)).collect::<Vec<_>>();
+        assert_eq!(import(&mut conn, msgs, true), 1000);
+        assert_eq!(spans(&conn), vec![(None, Some(id(1000)))]);
+
+        // Known messages are left untouched
+        let msgs = (990..=1010).map(|id| msg(id, None)).collect::<Vec<_>>();
+        assert_eq!(import(&mut conn, msgs, true), 10);
+        assert_eq!(spans(&conn), vec![(None, Some(id(1010)))]);
+    }
+
+    fn msg_ids(conn: &Connection) -> Vec<MessageId> {
+        conn.prepare("SELECT id FROM euph_msgs WHERE domain = ? AND room = ? ORDER BY id ASC")
+            .unwrap()
+            .query_map([&room().domain, &room().name], |row| {
+                row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap()
+    }
+
+    fn tree_ids(conn: &Connection) -> Vec<MessageId> {
+        conn.prepare("SELECT id FROM euph_trees WHERE domain = ? AND room = ? ORDER BY id ASC")
+            .unwrap()
+            .query_map([&room().domain, &room().name], |row| {
+                row.get::<_, WSnowflake>(0).map(|s| MessageId(s.0))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap()
+    }
+
+    fn search(conn: &mut Connection, query: &str) -> Vec<MessageId> {
+        let mut ids = SearchMsgs {
+            room: room(),
+            hide_deleted: false,
+            query: query.to_string(),
+            amount: 100,
+        }
+        .run(conn)
+        .unwrap()
+        .into_iter()
+        .map(|msg| msg.id)
+        .collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids
+    }
+
+    fn prune(conn: &mut Connection, keep_newest: usize) -> usize {
+        Prune {
+            room: room(),
+            older_than: None,
+            keep_newest: Some(keep_newest),
+        }
+        .run(conn)
+        .unwrap()
+    }
+
+    #[test]
+    fn prune_updates_spans_and_gaps() {
+        let mut conn = open();
+        insert(
+            &mut conn,
+            (1..=10).map(|id| msg(id, None)).collect(),
+            vec![(Some(id(1)), Some(id(3))), (Some(id(6)), Some(id(10)))],
+        );
+        assert_eq!(gaps(&mut conn), vec![(None, id(1)), (Some(id(3)), id(6))]);
+
+        assert_eq!(prune(&mut conn, 6), 4);
+        assert_eq!(msg_ids(&conn), (5..=10).map(id).collect::<Vec<_>>());
+        assert_eq!(spans(&conn), vec![(Some(id(6)), Some(id(10)))]);
+
+        // The gap before the remaining span only contains pruned messages
+        assert_eq!(prune(&mut conn, 5), 1);
+        assert_eq!(spans(&conn), vec![(Some(id(6)), Some(id(10)))]);
+        assert_eq!(gaps(&mut conn), vec![]);
+    }
+
+    #[test]
+    fn prune_keeps_pruned_msgs_out() {
+        let mut conn = open();
+        insert(
+            &mut conn,
+            (1..=10).map(|id| msg(id, None)).collect(),
+            vec![],
+        );
+        prune(&mut conn, 5);
+
+        insert(
+            &mut conn,
+            (1..=10).map(|id| msg(id, None)).collect(),
+            vec![(Some(id(1)), Some(id(10)))],
+        );
+        assert_eq!(msg_ids(&conn), (6..=10).map(id).collect::<Vec<_>>());
+        assert_eq!(spans(&conn), vec![(Some(id(6)), Some(id(10)))]);
+    }
+
+    #[test]
+    fn prune_updates_fts_and_trees() {
+        let mut conn = open();
+        insert(
+            &mut conn,
+            vec![
+                msg(1, None),
+                msg(2, Some(1)),
+                msg(3, None),
+                msg(4, Some(3)),
+                msg(5, None),
+                msg(6, Some(2)),
+            ],
+            vec![],
+        );
+        assert_eq!(
+            search(&mut conn, "hello"),
+            (1..=6).map(id).collect::<Vec<_>>()
+        );
+        assert_eq!(tree_ids(&conn), vec![id(1), id(3), id(5)]);
+
+        assert_eq!(prune(&mut conn, 3), 3);
+        assert_eq!(search(&mut conn, "hello"), vec![id(4), id(5), id(6)]);
+
+        // Tree 1 lost all its messages, tree 3 still contains a reply to its
+        // pruned root, and the reply to the pruned message 2 now starts its
+        // own tree.
+        assert_eq!(tree_ids(&conn), vec![id(2), id(3), id(5)]);
+
+        // The full-text index must not contain stale entries
+        let fts_rows = conn
+            .query_row(
+                "SELECT COUNT(*) FROM euph_msgs_fts WHERE euph_msgs_fts MATCH ?",
+                params!["hello"],
+                |row| row.get::<_, usize>(0),
+            )
+            .unwrap();
+        assert_eq!(fts_rows, 3);
+    }
+
+    fn fsck(conn: &mut Connection, fix: bool) -> FsckReport {
+        let tx = conn.transaction().unwrap();
+        let report = fsck_room(&tx, room(), false, fix).unwrap();
+        tx.commit().unwrap();
+        report
+    }
+
+    #[test]
+    fn fsck_repairs_spans() {
+        let mut conn = open();
+
+        // Message 6 is missing, but message 7 replies to it
+        let mut msgs = (1..=10)
+            .filter(|id| *id != 6)
+            .map(|id| msg(id, None))
+            .collect::<Vec<_>>();
+        msgs[5].parent = Some(id(6));
+        insert(&mut conn, msgs, vec![]);
+
+        for (start, end) in [(1, 3), (2, 4), (9, 8)] {
+            conn.execute(
+                "INSERT INTO euph_spans (domain, room, start, end) VALUES (?, ?, ?, ?)",
+                params![
+                    room().domain,
+                    room().name,
+                    WSnowflake(Snowflake(start)),
+                    WSnowflake(Snowflake(end)),
+                ],
+            )
+            .unwrap();
+        }
+
+        let report = fsck(&mut conn, true);
+        assert!(!report.is_ok());
+        assert_eq!(report.malformed_spans, 1);
+        assert_eq!(report.overlapping_spans, 1);
+        assert_eq!(report.orphaned_parents, 1);
+        assert_eq!(report.missing_parents_in_spans, 0);
+        assert_eq!(report.msgs_outside_spans, 5);
+
+        // Messages outside of spans stay outside since messages between them,
+        // like the missing parent, might not be known yet
+        assert_eq!(spans(&conn), vec![(Some(id(1)), Some(id(4)))]);
+
+        let report = fsck(&mut conn, false);
+        assert!(report.is_ok());
+        assert_eq!(report.orphaned_parents, 1);
+        assert_eq!(report.missing_parents_in_spans, 0);
+        assert_eq!(report.msgs_outside_spans, 5);
+    }
+}
@@ -1,7 +1,7 @@
 use rusqlite::Transaction;
 use vault::Migration;
 
-pub const MIGRATIONS: [Migration; 3] = [m1, m2, m3];
+pub const MIGRATIONS: [Migration; 9] = [m1, m2, m3, m4, m5, m6, m7, m8, m9];
 
 fn eprint_status(nr: usize, total: usize) {
     eprintln!("Migrating vault from {} to {} (out of {total})", nr, nr + 1);
@@ -222,3 +222,215 @@ fn m3(tx: &mut Transaction<'_>, nr: usize, total: usize) -> rusqlite::Result<()>
 
     Ok(())
 }
+
+fn m4(tx: &mut Transaction<'_>, nr: usize, total: usize) -> rusqlite::Result<()> {
+    eprint_status(nr, total);
+    println!("  This migration might take quite a while.");
+    println!("  Aborting it will not corrupt your vault.");
+
+    // Full-text index over message contents and nicks.
+    //
+    // The index is maintained manually instead of via triggers because
+    // virtual tables can't be used in triggers with `trusted_schema` disabled.
+    // Since `euph_msgs` has no explicit rowid column, its rowids may change
+    // when vacuuming, so the index must be rebuilt afterwards.
+    tx.execute_batch(
+        "
+        CREATE VIRTUAL TABLE euph_msgs_fts USING fts5 (
+            content,
+            name,
+            content = 'euph_msgs',
+            content_rowid = 'rowid'
+        );
+
+        INSERT INTO euph_msgs_fts (euph_msgs_fts)
+        VALUES ('rebuild');
+        ",
+    )
+}
+
+fn m5(tx: &mut Transaction<'_>, nr: usize, total: usize) -> rusqlite::Result<()> {
+    eprint_status(nr, total);
+
+    // Messages older than this id were deleted on purpose and should not be
+    // downloaded again.
+    tx.execute_batch(
+        "
+        ALTER TABLE euph_rooms
+        ADD COLUMN pruned_before INT;
+        ",
+    )
+}
+
+fn m6(tx: &mut Transaction<'_>, nr: usize, total: usize) -> rusqlite::Result<()> {
+    eprint_status(nr, total);
+    println!("  This migration might take quite a while.");
+    println!("  Aborting it will not corrupt your vault.");
+
+    // Cache ids of tree roots. This cache used to be a temporary table that
+    // was rebuilt on every startup.
+    tx.execute_batch(
+        "
+        CREATE TABLE euph_trees (
+            domain TEXT NOT NULL,
+            room   TEXT NOT NULL,
+            id     INT  NOT NULL,
+
+            PRIMARY KEY (domain, room, id),
+            FOREIGN KEY (domain, room) REFERENCES euph_rooms (domain, room)
+                ON DELETE CASCADE
+        ) STRICT;
+
+        INSERT INTO euph_trees (domain, room, id)
+        SELECT domain, room, id
+        FROM euph_msgs
+        WHERE parent IS NULL
+        UNION
+        SELECT domain, room, parent
+        FROM euph_msgs
+        WHERE parent IS NOT NULL
+        AND NOT EXISTS(
+            SELECT *
+            FROM euph_msgs AS parents
+            WHERE parents.domain = euph_msgs.domain
+            AND parents.room = euph_msgs.room
+            AND parents.id = euph_msgs.parent
+        );
+
+        CREATE TRIGGER et_insert_msg_without_parent
+        AFTER INSERT ON euph_msgs
+        WHEN new.parent IS NULL
+        BEGIN
+            INSERT OR IGNORE INTO euph_trees (domain, room, id)
+            VALUES (new.domain, new.room, new.id);
+        END;
+
+        CREATE TRIGGER et_insert_msg_with_parent
+        AFTER INSERT ON euph_msgs
+        WHEN new.parent IS NOT NULL
+        BEGIN
+            DELETE FROM euph_trees
+            WHERE domain = new.domain
+            AND room = new.room
+            AND id = new.id;
+
+            INSERT OR IGNORE INTO euph_trees (domain, room, id)
+            SELECT *
+            FROM (VALUES (new.domain, new.room, new.parent))
+            WHERE NOT EXISTS(
+                SELECT *
+                FROM euph_msgs
+                WHERE domain = new.domain
+                AND room = new.room
+                AND id = new.parent
+                AND parent IS NOT NULL
+            );
+        END;
+        ",
+    )
+}
+
+fn m7(tx: &mut Transaction<'_>, nr: usize, total: usize) -> rusqlite::Result<()> {
+    eprint_status(nr, total);
+    println!("  This migration might take quite a while.");
+    println!("  Aborting it will not corrupt your vault.");
+
+    // Cache amount of unseen messages per room. This cache used to be a
+    // temporary table that was rebuilt on every startup.
+    tx.execute_batch(
+        "
+        CREATE TABLE euph_unseen_counts (
+            domain TEXT NOT NULL,
+            room   TEXT NOT NULL,
+            amount INT  NOT NULL,
+
+            PRIMARY KEY (domain, room),
+            FOREIGN KEY (domain, room) REFERENCES euph_rooms (domain, room)
+                ON DELETE CASCADE
+        ) STRICT;
+
+        -- There must be an entry for every existing room.
+        INSERT INTO euph_unseen_counts (domain, room, amount)
+        SELECT domain, room, 0
+        FROM euph_rooms;
+
+        INSERT OR REPLACE INTO euph_unseen_counts (domain, room, amount)
+        SELECT domain, room, COUNT(*)
+        FROM euph_msgs
+        WHERE NOT seen
+        GROUP BY domain, room;
+
+        CREATE TRIGGER euc_insert_room
+        AFTER INSERT ON euph_rooms
+        BEGIN
+            INSERT INTO euph_unseen_counts (domain, room, amount)
+            VALUES (new.domain, new.room, 0);
+        END;
+
+        CREATE TRIGGER euc_insert_msg
+        AFTER INSERT ON euph_msgs
+        WHEN NOT new.seen
+        BEGIN
+            UPDATE euph_unseen_counts
+            SET amount = amount + 1
+            WHERE domain = new.domain
+            AND room = new.room;
+        END;
+
+        CREATE TRIGGER euc_update_msg
+        AFTER UPDATE OF seen ON euph_msgs
+        WHEN old.seen != new.seen
+        BEGIN
+            UPDATE euph_unseen_counts
+            SET amount = CASE WHEN new.seen THEN amount - 1 ELSE amount + 1 END
+            WHERE domain = new.domain
+            AND room = new.room;
+        END;
+
+        CREATE TRIGGER euc_delete_msg
+        AFTER DELETE ON euph_msgs
+        WHEN NOT old.seen
+        BEGIN
+            UPDATE euph_unseen_counts
+            SET amount = amount - 1
+            WHERE domain = old.domain
+            AND room = old.room;
+        END;
+        ",
+    )
+}
+
+fn m8(tx: &mut Transaction<'_>, nr: usize, total: usize) -> rusqlite::Result<()> {
+    eprint_status(nr, total);
+
+    // Bookmarks disappear together with the messages they point to.
+    tx.execute_batch(
+        "
+        CREATE TABLE euph_bookmarks (
+            domain  TEXT NOT NULL,
+            room    TEXT NOT NULL,
+            id      INT  NOT NULL,
+            note    TEXT,
+            created INT  NOT NULL,
+
+            PRIMARY KEY (domain, room, id),
+            FOREIGN KEY (domain, room, id) REFERENCES euph_msgs (domain, room, id)
+                ON DELETE CASCADE
+        ) STRICT;
+        ",
+    )
+}
+
+fn m9(tx: &mut Transaction<'_>, nr: usize, total: usize) -> rusqlite::Result<()> {
+    eprint_status(nr, total);
+
+    // Unseen deleted messages are subtracted from the unseen counts when
+    // deleted messages are hidden. There are usually very few of them.
+    tx.execute_batch(
+        "
+        CREATE INDEX euph_idx_msgs_domain_room_unseen_deleted
+        ON euph_msgs (domain, room)
+        WHERE NOT seen AND deleted IS NOT NULL;
+        ",
+    )
+}